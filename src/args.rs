@@ -0,0 +1,90 @@
+use std::error::Error;
+
+const DEFAULT_CONFIG: &str = "config/default.toml";
+pub const USAGE: &str =
+    "usage: taskmaster [--headless | --daemon] [config file]\n       taskmaster check [config file]";
+
+/// Command line arguments of taskmaster
+#[derive(Debug, PartialEq, Eq)]
+pub struct Args {
+    pub config_path: String,
+    /// run the supervision without the terminal interface
+    pub headless: bool,
+    /// detach in the background, implies headless
+    pub daemon: bool,
+    /// only report the problems of the configuration file
+    pub check: bool,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, Box<dyn Error>> {
+        let mut config_path = None;
+        let mut headless = false;
+        let mut daemon = false;
+        let mut check = false;
+        for arg in args {
+            match arg.as_str() {
+                "--headless" => headless = true,
+                "-d" | "--daemon" => daemon = true,
+                "-h" | "--help" => return Err(USAGE.into()),
+                flag if flag.starts_with('-') => {
+                    return Err(format!("unknown option `{flag}`\n{USAGE}").into())
+                }
+                "check" if config_path.is_none() && !check => check = true,
+                _ if config_path.is_some() => {
+                    return Err(format!("only one configuration file can be given\n{USAGE}").into())
+                }
+                _ => config_path = Some(arg),
+            }
+        }
+        Ok(Self {
+            config_path: config_path.unwrap_or(DEFAULT_CONFIG.to_string()),
+            headless: headless || daemon,
+            daemon,
+            check,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Args;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|a| a.to_string())).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn default() {
+        let args = parse(&[]).unwrap();
+        assert_eq!(args.config_path, super::DEFAULT_CONFIG);
+        assert!(!args.headless);
+    }
+    #[test]
+    fn headless() {
+        let args = parse(&["--headless", "tests/tests.toml"]).unwrap();
+        assert_eq!(args.config_path, "tests/tests.toml");
+        assert!(args.headless);
+    }
+    #[test]
+    fn daemon() {
+        let args = parse(&["-d"]).unwrap();
+        assert!(args.daemon);
+        assert!(args.headless);
+    }
+    #[test]
+    fn unknown_flag() {
+        assert!(parse(&["--nope"]).is_err());
+    }
+    #[test]
+    fn check() {
+        let args = parse(&["check", "tests/tests.toml"]).unwrap();
+        assert!(args.check);
+        assert_eq!(args.config_path, "tests/tests.toml");
+        assert!(!parse(&["tests/tests.toml"]).unwrap().check);
+    }
+    #[test]
+    fn two_configs() {
+        assert!(parse(&["a.toml", "b.toml"]).is_err());
+    }
+}
@@ -0,0 +1,68 @@
+//! Command line client for the taskmaster control socket.
+//!
+//! usage: taskmasterctl [-s <socket>] <command> [argument]
+//!
+//! The commands are the same as in the taskmaster shell:
+//! start, stop, restart, reload, status, loglevel and quit.
+
+use std::{
+    env::args,
+    io::{Read, Write},
+    net::Shutdown,
+    os::unix::net::UnixStream,
+    process::ExitCode,
+};
+
+const DEFAULT_SOCKET: &str = "/tmp/taskmaster.sock";
+const USAGE: &str = "usage: taskmasterctl [-s <socket>] <start|stop|restart|reload|status|loglevel|quit> [argument]";
+
+fn main() -> ExitCode {
+    let mut args = args().skip(1).peekable();
+    let mut socket = DEFAULT_SOCKET.to_string();
+    if args.peek().is_some_and(|a| a == "-s" || a == "--socket") {
+        args.next();
+        match args.next() {
+            Some(path) => socket = path,
+            None => {
+                eprintln!("{USAGE}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+    let command = args.collect::<Vec<_>>().join(" ");
+    if command.is_empty() || command == "-h" || command == "--help" {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    }
+
+    let response = match send(&socket, &command) {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("taskmasterctl: {socket}: {e}");
+            return ExitCode::from(2);
+        }
+    };
+    match response.split_once('\n') {
+        Some(("ok", message)) => {
+            print!("{message}");
+            ExitCode::SUCCESS
+        }
+        Some(("error", message)) => {
+            eprint!("{message}");
+            ExitCode::FAILURE
+        }
+        _ => {
+            eprintln!("taskmasterctl: invalid response from taskmaster: {response:?}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn send(socket: &str, command: &str) -> std::io::Result<String> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(format!("{command}\n").as_bytes())?;
+    stream.shutdown(Shutdown::Write)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response)
+}
@@ -0,0 +1,532 @@
+//! `taskmaster check`: everything wrong with a configuration file, found without applying it.
+//!
+//! Besides the errors of `Config::load`, the unknown keys, like the options of a supervisord
+//! file taskmaster ignores, are reported with the key they are probably meant to be, and the
+//! programs are checked for what would only fail when spawning them: a command not found, a
+//! missing user or directory, a malformed `env` entry.
+
+use super::{supervisord, Config, Group};
+use crate::{http::HttpConfig, program::Program};
+use serde::{
+    de::{self, DeserializeOwned, MapAccess, SeqAccess, Visitor},
+    forward_to_deserialize_any, Deserialize, Deserializer,
+};
+use std::{
+    env, fmt, fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+use toml::{Spanned, Value};
+use tracing::subscriber::NoSubscriber;
+
+/// the taskmaster key for the supervisord option, which the distance doesn't find
+const SUPERVISORD_KEYS: [(&str, &str); 9] = [
+    ("numprocs", "processes"),
+    ("autostart", "start_policy"),
+    ("autorestart", "restart_policy"),
+    ("startsecs", "min_runtime"),
+    ("startretries", "max_restarts"),
+    ("exitcodes", "valid_exit_codes"),
+    ("stopwaitsecs", "graceful_timeout"),
+    ("directory", "cwd"),
+    ("environment", "env"),
+];
+
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub file: PathBuf,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{line}: {}", self.file.display(), self.message),
+            None => write!(f, "{}: {}", self.file.display(), self.message),
+        }
+    }
+}
+
+/// the programs of an included file, the unknown keys being reported separately
+#[derive(Deserialize)]
+struct Programs {
+    #[serde(default)]
+    #[allow(dead_code)]
+    program: Vec<Program>,
+}
+
+/// the keys of a program table and their line, to place its problems
+type Keys = Vec<(String, usize)>;
+
+/// every problem of the configuration file and the files it includes
+pub fn check(path: &Path) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    // the file and keys of each program, in the order of Config::load
+    let mut programs = Vec::new();
+    let Some(mut config) = check_file::<Config>(path, &mut problems, &mut programs) else {
+        return problems;
+    };
+    config.path = path.to_path_buf();
+    let mut parsed = true;
+    match config.included_files() {
+        Ok(files) => {
+            for file in files {
+                parsed &= check_file::<Programs>(&file, &mut problems, &mut programs).is_some();
+            }
+        }
+        Err(e) => problems.push(Problem::new(path, None, e.to_string())),
+    }
+    if !parsed {
+        return problems;
+    }
+    match Config::load(path) {
+        Ok(config) => {
+            for (program, (file, keys)) in config.program.iter().zip(&programs) {
+                for (key, message) in check_program(program) {
+                    let line = keys
+                        .iter()
+                        .find(|(k, _)| k == key)
+                        .or(keys.first())
+                        .map(|(_, line)| *line);
+                    problems.push(Problem::new(file, line, message));
+                }
+            }
+        }
+        Err(e) => problems.push(Problem::new(path, None, e.to_string())),
+    }
+    problems
+}
+
+impl Problem {
+    fn new(file: &Path, line: Option<usize>, message: String) -> Self {
+        Problem {
+            file: file.to_path_buf(),
+            line,
+            message,
+        }
+    }
+}
+
+/// parse a file as T, reporting its unknown keys, and record where its programs are
+fn check_file<T: DeserializeOwned>(
+    file: &Path,
+    problems: &mut Vec<Problem>,
+    programs: &mut Vec<(PathBuf, Keys)>,
+) -> Option<T> {
+    let raw = match fs::read_to_string(file) {
+        Ok(raw) => raw,
+        Err(e) => {
+            problems.push(Problem::new(file, None, e.to_string()));
+            return None;
+        }
+    };
+    if supervisord::is_supervisord(file) {
+        return check_supervisord(file, &raw, problems, programs);
+    }
+    let line = |offset: usize| raw[..offset].matches('\n').count() + 1;
+    let error = |e: toml::de::Error| {
+        let line = e.span().map(|span| line(span.start));
+        Problem::new(file, line, e.message().trim().to_string())
+    };
+    let root = match toml::from_str::<Item>(&raw) {
+        Ok(Item::Table(root)) => root,
+        Ok(_) => return None,
+        Err(e) => {
+            problems.push(error(e));
+            return None;
+        }
+    };
+    let mut unknown = |keys: &[(Spanned<String>, Item)], known: &[&str], table: &str| {
+        for (key, _) in keys {
+            if known.contains(&key.get_ref().as_str()) {
+                continue;
+            }
+            let mut message = format!("unknown key `{}` in {table}", key.get_ref());
+            if let Some(suggestion) = suggest(key.get_ref(), known) {
+                message.push_str(&format!(", did you mean `{suggestion}`?"));
+            }
+            problems.push(Problem::new(file, Some(line(key.span().start)), message));
+        }
+    };
+    unknown(&root, fields::<T>(), "the configuration");
+    for (key, item) in &root {
+        match (key.get_ref().as_str(), item) {
+            ("http", Item::Table(http)) => unknown(http, fields::<HttpConfig>(), "http"),
+            ("group", Item::Array(tables)) => {
+                for table in tables {
+                    if let Item::Table(table) = table {
+                        unknown(table, fields::<Group>(), "group");
+                    }
+                }
+            }
+            ("program", Item::Array(tables)) => {
+                for table in tables {
+                    let Item::Table(table) = table else { continue };
+                    unknown(table, fields::<Program>(), "program");
+                    let keys = table
+                        .iter()
+                        .map(|(k, _)| (k.get_ref().clone(), line(k.span().start)))
+                        .collect();
+                    programs.push((file.to_path_buf(), keys));
+                }
+            }
+            _ => (),
+        }
+    }
+    match toml::from_str(&raw) {
+        Ok(parsed) => Some(parsed),
+        Err(e) => {
+            problems.push(error(e));
+            None
+        }
+    }
+}
+
+/// parse a supervisord file as T, reporting the sections and options taskmaster ignores
+fn check_supervisord<T: DeserializeOwned>(
+    file: &Path,
+    raw: &str,
+    problems: &mut Vec<Problem>,
+    programs: &mut Vec<(PathBuf, Keys)>,
+) -> Option<T> {
+    let sections = match supervisord::sections(raw) {
+        Ok(sections) => sections,
+        Err(e) => {
+            problems.push(Problem::new(file, None, e));
+            return None;
+        }
+    };
+    for (section, line, options) in &sections {
+        let kind = section.split(':').next().unwrap_or_default();
+        let Some(known) = supervisord::options(section) else {
+            if !supervisord::IGNORED_SECTIONS.contains(&kind) {
+                let message = format!("unsupported section [{section}]");
+                problems.push(Problem::new(file, Some(*line), message));
+            }
+            continue;
+        };
+        for (key, _, line) in options.iter().filter(|(key, ..)| !known.contains(&&**key)) {
+            let mut message = format!("unsupported option `{key}` in [{section}]");
+            // a taskmaster key, or a typo
+            let suggestion = SUPERVISORD_KEYS
+                .iter()
+                .find(|(_, field)| field == key)
+                .map(|(option, _)| *option)
+                .filter(|option| known.contains(option))
+                .or_else(|| suggest(key, known));
+            if let Some(suggestion) = suggestion {
+                message.push_str(&format!(", did you mean `{suggestion}`?"));
+            }
+            problems.push(Problem::new(file, Some(*line), message));
+        }
+        if matches!(kind, "program" | "eventlistener") {
+            let keys = options
+                .iter()
+                .map(|(key, _, line)| (taskmaster_key(key).to_string(), *line))
+                .collect();
+            programs.push((file.to_path_buf(), keys));
+        }
+    }
+    // the unsupported options are logged as warnings by Config::load, not twice
+    let parsed = tracing::subscriber::with_default(NoSubscriber::default(), || {
+        supervisord::parse(raw, file)
+    });
+    parsed
+        .and_then(|table| Ok(Value::Table(table).try_into()?))
+        .map_err(|e| problems.push(Problem::new(file, None, e.to_string())))
+        .ok()
+}
+
+/// the taskmaster key of a supervisord option, to place the problems of its program
+fn taskmaster_key(option: &str) -> &str {
+    match SUPERVISORD_KEYS.iter().find(|(k, _)| *k == option) {
+        Some((_, key)) => key,
+        // stdout_logfile and stderr_logfile
+        None => option.strip_suffix("_logfile").unwrap_or(option),
+    }
+}
+
+/// what would fail when spawning the childs, with the key responsible
+fn check_program(program: &Program) -> Vec<(&'static str, String)> {
+    let mut problems = Vec::new();
+    // as for the first child, the expansion is checked by Config::load
+    let Ok(expanded) = program.expanded(0) else {
+        return problems;
+    };
+    let cwd = expanded.cwd.clone().unwrap_or_else(|| PathBuf::from("."));
+    let executable = |path: &Path| {
+        fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    };
+    let cmd = &expanded.cmd;
+    if cmd.components().count() > 1 {
+        if !executable(&cwd.join(cmd)) {
+            problems.push((
+                "command",
+                format!("command `{}` isn't an executable file", cmd.display()),
+            ));
+        }
+    } else {
+        // the PATH of the childs
+        let path = expanded
+            .env
+            .iter()
+            .find_map(|var| var.strip_prefix("PATH="))
+            .map(String::from)
+            .or_else(|| env::var("PATH").ok())
+            .unwrap_or_default();
+        if !env::split_paths(&path).any(|dir| executable(&dir.join(cmd))) {
+            problems.push((
+                "command",
+                format!("command `{}` not found in PATH", cmd.display()),
+            ));
+        }
+    }
+    if let Some(user) = &program.user {
+        if users::get_user_by_name(user).is_none() {
+            problems.push(("user", format!("user `{user}` doesn't exist")));
+        }
+    }
+    for var in expanded.env.iter().filter(|var| !var.contains('=')) {
+        problems.push(("env", format!("env entry `{var}` isn't KEY=value")));
+    }
+    if let Some(dir) = &expanded.cwd {
+        if !dir.is_dir() {
+            problems.push(("cwd", format!("cwd `{}` isn't a directory", dir.display())));
+        }
+    }
+    if let Some(stdin) = &program.stdin {
+        if !cwd.join(stdin).is_file() {
+            problems.push((
+                "stdin",
+                format!("stdin `{}` doesn't exist", stdin.display()),
+            ));
+        }
+    }
+    for (key, output) in [("stdout", &expanded.stdout), ("stderr", &expanded.stderr)] {
+        let Some(output) = output else { continue };
+        let dir = cwd.join(output).parent().map(Path::to_path_buf);
+        if !dir.is_some_and(|dir| dir.is_dir()) {
+            problems.push((
+                key,
+                format!(
+                    "the directory of {key} `{}` doesn't exist",
+                    output.display()
+                ),
+            ));
+        }
+    }
+    if program.umask.is_some_and(|umask| umask > 0o777) {
+        problems.push(("umask", "umask is more than 0o777".to_string()));
+    }
+    problems
+}
+
+/// the known key closest to a typo
+fn suggest<'a>(key: &str, known: &[&'a str]) -> Option<&'a str> {
+    if let Some((_, field)) = SUPERVISORD_KEYS.iter().find(|(k, _)| *k == key) {
+        return known.iter().find(|k| *k == field).copied();
+    }
+    let words: Vec<_> = key.split('_').filter(|w| w.len() > 2).collect();
+    known
+        .iter()
+        .filter(|k| distance(key, k) <= 2 || k.split('_').any(|w| words.contains(&w)))
+        .min_by_key(|k| distance(key, k))
+        .copied()
+}
+
+/// the Levenshtein distance
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// a TOML document keeping the place of the keys
+enum Item {
+    Table(Vec<(Spanned<String>, Item)>),
+    Array(Vec<Item>),
+    Value,
+}
+
+impl<'de> Deserialize<'de> for Item {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ItemVisitor)
+    }
+}
+
+struct ItemVisitor;
+
+impl<'de> Visitor<'de> for ItemVisitor {
+    type Value = Item;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a TOML value")
+    }
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Item, A::Error> {
+        let mut keys = Vec::new();
+        while let Some(key) = map.next_key()? {
+            keys.push((key, map.next_value()?));
+        }
+        Ok(Item::Table(keys))
+    }
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Item, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Item::Array(items))
+    }
+    fn visit_bool<E>(self, _: bool) -> Result<Item, E> {
+        Ok(Item::Value)
+    }
+    fn visit_i64<E>(self, _: i64) -> Result<Item, E> {
+        Ok(Item::Value)
+    }
+    fn visit_u64<E>(self, _: u64) -> Result<Item, E> {
+        Ok(Item::Value)
+    }
+    fn visit_f64<E>(self, _: f64) -> Result<Item, E> {
+        Ok(Item::Value)
+    }
+    fn visit_str<E>(self, _: &str) -> Result<Item, E> {
+        Ok(Item::Value)
+    }
+}
+
+/// the keys a struct is deserialized from
+fn fields<T: DeserializeOwned>() -> &'static [&'static str] {
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldsOf(&mut fields));
+    fields
+}
+
+/// a deserializer which only records the fields it is asked for
+struct FieldsOf<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldsOf<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("fields recorded"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+        ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, suggest, Problem};
+    use std::{fs, path::Path};
+    use tempfile::tempdir;
+
+    #[test]
+    fn typos() {
+        let known = ["stop_signal", "valid_exit_codes", "processes", "cwd"];
+        assert_eq!(suggest("exit_signal", &known), Some("stop_signal"));
+        assert_eq!(suggest("proceses", &known), Some("processes"));
+        assert_eq!(suggest("directory", &known), Some("cwd"));
+        assert_eq!(suggest("nothing", &known), None);
+    }
+    #[test]
+    fn unknown_key() {
+        let path = Path::new("tests/cant_kill.toml");
+        assert_eq!(
+            check(path),
+            [Problem {
+                file: path.to_path_buf(),
+                line: Some(7),
+                message: "unknown key `exit_signal` in program, did you mean `stop_signal`?"
+                    .to_string()
+            }]
+        );
+        assert!(check(Path::new("config/multiple.toml")).is_empty());
+    }
+    #[test]
+    fn spawn_problems() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("taskmaster.toml");
+        fs::write(
+            &path,
+            r#"include = ["*.d.toml"]
+
+[[program]]
+name = "missing"
+command = "taskmaster-no-such-command"
+user = "taskmaster-no-such-user"
+env = ["A=1", "B"]
+"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("more.d.toml"),
+            "[[program]]\nname = \"more\"\ncommand = \"ls\"\n\ncwd = \"/no/such/dir\"\n",
+        )
+        .unwrap();
+        let problems: Vec<_> = check(&path).iter().map(|p| p.to_string()).collect();
+        let main = path.display();
+        let more = dir.path().join("more.d.toml");
+        let more = more.display();
+        assert_eq!(
+            problems,
+            [
+                format!("{main}:5: command `taskmaster-no-such-command` not found in PATH"),
+                format!("{main}:6: user `taskmaster-no-such-user` doesn't exist"),
+                format!("{main}:7: env entry `B` isn't KEY=value"),
+                format!("{more}:5: cwd `/no/such/dir` isn't a directory"),
+            ]
+        );
+    }
+    #[test]
+    fn supervisord() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("supervisord.conf");
+        fs::write(
+            &path,
+            "[supervisord]\npidfile = /tmp/taskmaster-check.pid\n\n[program:web]\ncommand = ls\ndirectory = /no/such/dir\nautostrat = false\nprocesses = 2\nprocess_name = web\n\n[programm:db]\ncommand = ls\n",
+        )
+        .unwrap();
+        let problems: Vec<_> = check(&path).iter().map(|p| p.to_string()).collect();
+        let file = path.display();
+        assert_eq!(
+            problems,
+            [
+                format!("{file}:7: unsupported option `autostrat` in [program:web], did you mean `autostart`?"),
+                format!("{file}:8: unsupported option `processes` in [program:web], did you mean `numprocs`?"),
+                format!("{file}:9: unsupported option `process_name` in [program:web]"),
+                format!("{file}:11: unsupported section [programm:db]"),
+                format!("{file}:6: cwd `/no/such/dir` isn't a directory"),
+            ]
+        );
+    }
+    #[test]
+    fn syntax_error() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("taskmaster.toml");
+        fs::write(&path, "[[program]]\nname = \"a\"\ncommand = 3\n").unwrap();
+        let problems = check(&path);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].line, Some(3));
+    }
+}
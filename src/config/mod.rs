@@ -1,11 +1,22 @@
+pub mod check;
 pub mod signal;
+pub mod supervisord;
 
-use crate::program::{generate_name, Program};
+use crate::{
+    http::HttpConfig,
+    program::{child::Status, generate_name, orphan, socket, Program, StartPolicy},
+};
 use serde::Deserialize;
 use serde_with::{serde_as, DisplayFromStr};
 pub use signal::Signal;
-use std::{collections::HashSet, error::Error, fs, path::Path};
-use tracing::{error, info, instrument, warn, Level};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs, iter, mem,
+    path::{Path, PathBuf},
+};
+use toml::{Table, Value};
+use tracing::{debug, error, info, instrument, warn, Level};
 use tracing_subscriber::{reload::Handle, EnvFilter, Registry};
 
 #[serde_as]
@@ -14,16 +25,72 @@ pub struct Config {
     #[serde(default = "default_loglevel")]
     #[serde_as(as = "DisplayFromStr")]
     pub loglevel: Level,
+    /// unix socket on which taskmasterctl commands are received
+    #[serde(default = "default_socket")]
+    pub socket: PathBuf,
+    /// file in which the pid of taskmaster is written
+    pub pidfile: Option<PathBuf>,
+    /// file in which the running childs are saved, to adopt them back after a restart
+    pub statefile: Option<PathBuf>,
+    /// optional JSON API
+    pub http: Option<HttpConfig>,
+    /// globs of files with more `[[program]]`, relative to this file
+    #[serde(default)]
+    pub include: Vec<String>,
     pub program: Vec<Program>,
+    /// programs controlled together, as `<group>:*` or `<group>:<program>`
+    #[serde(default)]
+    pub group: Vec<Group>,
 
     #[serde(skip)]
     pub tracing_filter_handle: Option<Handle<EnvFilter, Registry>>,
     #[serde(skip)]
     pub program_deletions: Vec<String>,
+    /// programs restarted for a configuration change, stopped once their dependents are and
+    /// started again once their dependencies are
+    #[serde(skip)]
+    pub program_restarts: Vec<String>,
+    /// the file this configuration was loaded from
+    #[serde(skip)]
+    pub path: PathBuf,
+    #[serde(skip)]
+    pub pending_quit: bool,
+    #[serde(skip)]
+    pub force_quit: bool,
+    /// taskmaster is the init process of a pid namespace, like in a container
+    #[serde(skip)]
+    pub init: bool,
+    /// taskmaster re-executes itself once no event listener is busy
+    #[serde(skip)]
+    pub pending_reexec: bool,
+    /// serial of the last event sent to the listeners
+    #[serde(skip)]
+    event_serial: usize,
+    /// the state changes of the childs so far, the state file is only saved after one
+    #[serde(skip)]
+    pub changes: usize,
+}
+/// a `[[group]]` of programs
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Group {
+    pub name: String,
+    /// the names of its programs
+    pub programs: Vec<String>,
+}
+/// a file included by the main configuration file
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Included {
+    #[serde(default)]
+    program: Vec<Program>,
 }
+
 fn default_loglevel() -> Level {
     Level::INFO
 }
+pub fn default_socket() -> PathBuf {
+    PathBuf::from("/tmp/taskmaster.sock")
+}
 
 impl Config {
     pub fn reload_tracing_level(&mut self) -> Result<(), Box<dyn Error>> {
@@ -34,67 +101,526 @@ impl Config {
         Ok(())
     }
 
+    /// start the graceful shutdown of every program. taskmaster quits once they are all stopped
+    pub fn quit(&mut self) {
+        info!("Gracefully shutting down programs");
+        self.pending_quit = true;
+        self.pending_reexec = false;
+        self.program_restarts.clear();
+        // none is restarted anymore, only the signals wait for the dependents to stop
+        for program in &mut self.program {
+            program.force_restart = false;
+            program.waits_dependencies = false;
+            // the exited ones keep their exit code
+            let stopping: Vec<_> = (0..program.childs.len())
+                .filter(|&i| {
+                    let child = &program.childs[i];
+                    child.status.is_running() || child.wants_restart(program)
+                })
+                .collect();
+            for index in stopping {
+                program.childs[index].stopped = true;
+            }
+        }
+        self.stop_next();
+    }
+
+    /// stop the programs whose dependents are stopped, the others wait for them. as init,
+    /// they are stopped one after another, in the reverse order of the configuration: the
+    /// last one which can be stopped is, the others wait for it
+    pub fn stop_next(&mut self) {
+        let mut stoppable: Vec<_> = (0..self.program.len())
+            .filter(|&i| !self.program[i].all_stopped() && self.dependents_stopped(i))
+            .collect();
+        if self.init {
+            stoppable = stoppable.pop().into_iter().collect();
+        }
+        for index in stoppable {
+            self.program[index].stop();
+        }
+    }
+
+    /// the programs which depend on the program `index` are all stopped
+    fn dependents_stopped(&self, index: usize) -> bool {
+        let name = &self.program[index].name;
+        self.program
+            .iter()
+            .filter(|p| p.depends_on.contains(name))
+            .all(Program::all_stopped)
+    }
+
+    /// every program of `depends_on` is running
+    pub fn dependencies_running(&self, index: usize) -> bool {
+        self.program[index].depends_on.iter().all(|name| {
+            self.program.iter().any(|p| {
+                &p.name == name
+                    && !p.childs.is_empty()
+                    && p.childs
+                        .iter()
+                        .all(|c| matches!(c.status, Status::Running(_)))
+            })
+        })
+    }
+
+    /// start a program, or wait for its dependencies to be running
+    pub fn start_program(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        let index = self
+            .program
+            .iter()
+            .position(|p| p.name == name)
+            .ok_or_else(|| format!("Program `{name}` not found"))?;
+        if !self.dependencies_running(index) {
+            info!(name, "Waiting for the dependencies to be running");
+            self.program[index].waits_dependencies = true;
+            return Ok(());
+        }
+        self.program[index].start()
+    }
+
+    /// start the programs waiting for their dependencies once these are running, unless
+    /// shutting down
+    pub fn start_waiting(&mut self) {
+        if self.pending_quit {
+            return;
+        }
+        for index in 0..self.program.len() {
+            if !self.program[index].waits_dependencies || !self.dependencies_running(index) {
+                continue;
+            }
+            let program = &mut self.program[index];
+            program.waits_dependencies = false;
+            info!(name = program.name, "Dependencies running");
+            if let Err(e) = program.start() {
+                error!(error = e, name = program.name, "starting program");
+            }
+        }
+    }
+
+    /// every started program has exited, and none will be restarted. the on-demand programs
+    /// can always be started by a connection
+    pub fn all_exited(&self) -> bool {
+        self.program.iter().any(|p| !p.childs.is_empty())
+            && self
+                .program
+                .iter()
+                .all(|p| p.start_policy != StartPolicy::OnDemand)
+            && self.program.iter().all(|p| {
+                p.all_stopped() && !p.force_restart && !p.childs.iter().any(|c| c.wants_restart(p))
+            })
+    }
+
+    /// as init, the exit code of the process which failed first: its own non-zero one not in
+    /// valid_exit_codes, or 128 + the signal which terminated it.
+    /// the processes stopped on request don't count
+    pub fn exit_code(&self) -> u8 {
+        let failure = self
+            .program
+            .iter()
+            .flat_map(|p| p.childs.iter().map(move |c| (p, c)))
+            .filter(|(_, c)| !c.stopped)
+            .filter_map(|(p, c)| match c.status {
+                Status::Finished(at, code) if code != 0 && !p.valid_exit_codes.contains(&code) => {
+                    Some((at, code))
+                }
+                Status::Terminated(at, signal) => Some((at, 128 + signal)),
+                _ => None,
+            })
+            .min_by_key(|(at, _)| *at)
+            .map(|(_, code)| code);
+        match failure {
+            // exit codes are truncated to 8 bits, a failure must not become a success
+            Some(code) if code % 256 == 0 => 1,
+            Some(code) => code as u8,
+            None => 0,
+        }
+    }
+
+    /// start the on-demand programs which received a connection, unless shutting down
+    pub fn start_on_demand(&mut self) {
+        if self.pending_quit {
+            return;
+        }
+        for program in &mut self.program {
+            program.start_on_demand();
+        }
+    }
+
+    /// send the state changes of the childs to the programs listening to them
+    pub fn dispatch_events(&mut self) {
+        let mut events = Vec::new();
+        for program in &mut self.program {
+            let transitions = mem::take(&mut program.transitions);
+            self.changes += transitions.len();
+            // listeners don't receive their own events, to avoid feedback loops
+            if !program.is_listener() {
+                events.extend(transitions);
+            }
+        }
+        for event in events {
+            self.event_serial += 1;
+            for program in &mut self.program {
+                program.queue_event(self.event_serial, &event);
+            }
+        }
+        for program in &mut self.program {
+            program.send_events();
+        }
+    }
+
+    /// give the descendants reparented to taskmaster back to their program, and reap the
+    /// unknown ones which exited
+    pub fn adopt_orphans(&mut self) {
+        for (pid, zombie) in orphan::children() {
+            if self.program.iter().any(|p| p.owns(pid)) {
+                continue;
+            }
+            let owner = orphan::owner(pid);
+            match self
+                .program
+                .iter_mut()
+                .find(|p| Some(&p.name) == owner.as_ref())
+            {
+                Some(program) => program.adopt(pid),
+                None if zombie => {
+                    debug!(pid, "Reaping an unknown process");
+                    orphan::reap(pid);
+                }
+                None => (),
+            }
+        }
+    }
+
     #[instrument(skip_all, fields(path = %file_path.as_ref().display()))]
     pub fn load(file_path: impl AsRef<Path>) -> Result<Config, Box<dyn Error>> {
         info!("Loading configuration file");
-        let raw_file = fs::read_to_string(file_path)?;
-        let mut config: Config = toml::from_str(&raw_file)?;
-        let mut names = HashSet::new();
-        for prog in &mut config.program {
+        let raw_file = fs::read_to_string(&file_path)?;
+        let mut config: Config = match supervisord::is_supervisord(file_path.as_ref()) {
+            true => Value::Table(supervisord::parse(&raw_file, file_path.as_ref())?).try_into()?,
+            false => toml::from_str(&raw_file)?,
+        };
+        config.path = file_path.as_ref().to_path_buf();
+        // or any password would be accepted
+        if config
+            .http
+            .as_ref()
+            .is_some_and(|http| http.token.is_empty())
+        {
+            return Err("http: the token can't be empty".into());
+        }
+        // the file each program comes from
+        let mut sources = vec![config.path.clone(); config.program.len()];
+        for file in config.included_files()? {
+            debug!(file = %file.display(), "Including configuration file");
+            let included = Config::load_included(&file)
+                .map_err(|e| format!("including {}: {e}", file.display()))?;
+            sources.extend(iter::repeat_n(file, included.program.len()));
+            config.program.extend(included.program);
+        }
+        let mut names = HashMap::new();
+        for (prog, source) in config.program.iter_mut().zip(&sources) {
+            for address in &prog.sockets {
+                socket::validate(address).map_err(|e| format!("program `{}`: {e}", prog.name))?;
+            }
+            if prog.start_policy == StartPolicy::OnDemand && prog.sockets.is_empty() {
+                return Err(format!(
+                    "program `{}`: an on-demand program needs sockets to listen on",
+                    prog.name
+                )
+                .into());
+            }
+            let dir = source
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            prog.here = fs::canonicalize(dir).unwrap_or(dir.to_path_buf());
+            // the variables are the same for every child but the process number
+            prog.expanded(0)
+                .map_err(|e| format!("program `{}`: {e}", prog.name))?;
             prog.name = prog
                 .name
                 .replace(' ', "_")
                 .trim_matches(['_', ' '])
                 .to_string();
-            if !prog.name.is_empty() && names.insert(prog.name.clone()) {
+            if !prog.name.is_empty() {
+                if let Some(first) = names.insert(prog.name.clone(), source) {
+                    return Err(format!(
+                        "program `{}` is defined twice, in {} and {}",
+                        prog.name,
+                        first.display(),
+                        source.display()
+                    )
+                    .into());
+                }
                 continue;
             }
             // there is 1124 power of 981 combinaisons, we are safe
             let new = generate_name();
             warn!(
-                "Renaming Program with command `{}` as `{}` because `{}` is already taken",
+                "Naming Program with command `{}` as `{}` because its name is empty",
                 prog.cmd.display(),
                 new,
-                prog.name
             );
+            names.insert(new.clone(), source);
             prog.name = new;
         }
+        for prog in &config.program {
+            if let Some(missing) = prog.depends_on.iter().find(|d| !names.contains_key(*d)) {
+                return Err(format!(
+                    "program `{}` depends on `{missing}`, which doesn't exist",
+                    prog.name
+                )
+                .into());
+            }
+        }
+        if let Some(cycle) = dependency_cycle(&config.program) {
+            return Err(
+                format!("dependency cycle between programs: {}", cycle.join(" -> ")).into(),
+            );
+        }
+        let mut groups = Vec::new();
+        for group in &config.group {
+            if group.name.is_empty() || group.name.contains(':') {
+                return Err(format!("invalid group name `{}`", group.name).into());
+            }
+            if groups.contains(&&group.name) {
+                return Err(format!("group `{}` is defined twice", group.name).into());
+            }
+            groups.push(&group.name);
+            if let Some(missing) = group.programs.iter().find(|p| !names.contains_key(*p)) {
+                return Err(format!("group `{}`: no program `{missing}`", group.name).into());
+            }
+        }
         info!(
             "Configuration file loaded with {} programs",
             config.program.len()
         );
         Ok(config)
     }
+
+    /// the programs targeted by a command: `<name>`, all the programs of a group with
+    /// `<group>:*`, or one of them with `<group>:<name>`
+    pub fn find_programs(&mut self, target: &str) -> Result<Vec<&mut Program>, String> {
+        let names = match target.split_once(':') {
+            Some((group, member)) => {
+                let group = self
+                    .group
+                    .iter()
+                    .find(|g| g.name == group)
+                    .ok_or_else(|| format!("Group `{group}` not found"))?;
+                match member {
+                    "*" => group.programs.clone(),
+                    _ if group.programs.iter().any(|p| p == member) => vec![member.to_string()],
+                    _ => {
+                        return Err(format!(
+                            "Program `{member}` not in the group `{}`",
+                            group.name
+                        ))
+                    }
+                }
+            }
+            None => vec![target.to_string()],
+        };
+        let programs: Vec<_> = self
+            .program
+            .iter_mut()
+            .filter(|p| names.contains(&p.name))
+            .collect();
+        if programs.is_empty() {
+            return Err(format!("Program `{target}` not found"));
+        }
+        Ok(programs)
+    }
+    /// the programs of an included file, in the format of its extension
+    fn load_included(file: &Path) -> Result<Included, Box<dyn Error>> {
+        let raw_file = fs::read_to_string(file)?;
+        if !supervisord::is_supervisord(file) {
+            return Ok(toml::from_str(&raw_file)?);
+        }
+        let mut table = supervisord::parse(&raw_file, file)?;
+        let programs = table.remove("program").unwrap_or(Value::Array(Vec::new()));
+        if !table.is_empty() {
+            warn!(file = %file.display(), "Only the programs of an included file are used");
+        }
+        let included = Table::from_iter([("program".to_string(), programs)]);
+        Ok(Value::Table(included).try_into()?)
+    }
+
+    /// the files matched by the `include` globs, in alphabetical order for each glob
+    fn included_files(&self) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+        let dir = glob::Pattern::escape(dir.to_str().ok_or("the path is invalid unicode")?);
+        let main = fs::canonicalize(&self.path)?;
+        let mut files: Vec<PathBuf> = Vec::new();
+        for pattern in &self.include {
+            let pattern = Path::new(&dir).join(pattern);
+            let pattern = pattern.to_str().ok_or("the path is invalid unicode")?;
+            for file in glob::glob(pattern).map_err(|e| format!("include `{pattern}`: {e}"))? {
+                let file = file?;
+                // matched by several globs, or the main file itself
+                let canonical = fs::canonicalize(&file)?;
+                if canonical == main
+                    || files
+                        .iter()
+                        .any(|f| fs::canonicalize(f).is_ok_and(|f| f == canonical))
+                {
+                    continue;
+                }
+                files.push(file);
+            }
+        }
+        Ok(files)
+    }
+
     pub fn update(&mut self, new: Config) -> Result<(), Box<dyn Error>> {
         if self.loglevel != new.loglevel {
             self.loglevel = new.loglevel;
             self.reload_tracing_level()?;
         }
-        for program in &mut self.program {
+        if self.socket != new.socket {
+            warn!(
+                socket = %new.socket.display(),
+                "Changing the control socket requires restarting taskmaster, ignoring"
+            );
+        }
+        if self.pidfile != new.pidfile {
+            warn!("Changing the pidfile requires restarting taskmaster, ignoring");
+        }
+        if self.statefile != new.statefile {
+            warn!("Changing the statefile requires restarting taskmaster, ignoring");
+        }
+        if self.http != new.http {
+            warn!("Changing the HTTP server requires restarting taskmaster, ignoring");
+        }
+        self.group = new.group;
+        // stopped once their dependents are, by stop_deleted
+        for program in &self.program {
             if !new.program.iter().any(|p| p.name == program.name) {
                 self.program_deletions.push(program.name.clone());
-                program.stop();
             }
         }
-        for mut new in new.program.into_iter() {
+        let mut restarts = Vec::new();
+        for new in new.program.into_iter() {
             if let Some(old) = self.program.iter_mut().find(|p| p.name == new.name) {
-                old.update(new);
-            } else {
-                if let Err(e) = new.start() {
-                    error!(error = e, "starting program");
+                if old.update(new) {
+                    restarts.push(old.name.clone());
                 }
+            } else {
+                let name = new.name.clone();
+                let index = self.program.len();
                 self.program.push(new);
+                let started = match self.program[index].start_policy {
+                    StartPolicy::OnDemand => self.program[index].listen(),
+                    _ => self.start_program(&name),
+                };
+                if let Err(e) = started {
+                    error!(error = e, name, "starting program");
+                }
             }
         }
+        // the running dependents of a restarted program are restarted with it
+        while let Some(name) = restarts.pop() {
+            if self.program_restarts.contains(&name) {
+                continue;
+            }
+            restarts.extend(
+                self.program
+                    .iter()
+                    .filter(|p| p.depends_on.contains(&name) && !p.all_stopped())
+                    .map(|p| p.name.clone()),
+            );
+            self.program_restarts.push(name);
+        }
+        self.stop_deleted();
+        self.restart_changed();
         Ok(())
     }
+
+    /// stop the changed programs whose dependents are stopped, and start the stopped ones once
+    /// none of their dependencies is still to be restarted
+    pub fn restart_changed(&mut self) {
+        let pending = mem::take(&mut self.program_restarts);
+        for name in &pending {
+            let Some(index) = self.program.iter().position(|p| &p.name == name) else {
+                continue;
+            };
+            let program = &self.program[index];
+            if !program.all_stopped() {
+                if self.dependents_stopped(index) {
+                    self.program[index].stop();
+                }
+            } else if !program.depends_on.iter().any(|d| pending.contains(d)) {
+                info!(name, "Restarting with the new configuration");
+                if let Err(e) = self.start_program(name) {
+                    error!(error = e, name, "restarting program");
+                }
+                continue;
+            }
+            self.program_restarts.push(name.clone());
+        }
+    }
+
+    /// stop the programs removed from the configuration whose dependents are stopped, and
+    /// remove the stopped ones
+    pub fn stop_deleted(&mut self) {
+        for name in mem::take(&mut self.program_deletions) {
+            let Some(index) = self.program.iter().position(|p| p.name == name) else {
+                continue;
+            };
+            if self.program[index].all_stopped() {
+                info!(name, "Removing program");
+                self.program.remove(index);
+                continue;
+            }
+            if self.dependents_stopped(index) {
+                self.program[index].stop();
+            }
+            self.program_deletions.push(name);
+        }
+    }
+}
+
+/// a cycle in the `depends_on` of the programs, as the names along it
+fn dependency_cycle(programs: &[Program]) -> Option<Vec<&str>> {
+    fn visit<'a>(
+        programs: &'a [Program],
+        name: &'a str,
+        path: &mut Vec<&'a str>,
+        done: &mut Vec<&'a str>,
+    ) -> Option<Vec<&'a str>> {
+        if let Some(start) = path.iter().position(|n| *n == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name);
+            return Some(cycle);
+        }
+        if done.contains(&name) {
+            return None;
+        }
+        let program = programs.iter().find(|p| p.name == name)?;
+        path.push(name);
+        for dependency in &program.depends_on {
+            if let Some(cycle) = visit(programs, dependency, path, done) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        done.push(name);
+        None
+    }
+    let mut done = Vec::new();
+    programs
+        .iter()
+        .find_map(|p| visit(programs, &p.name, &mut Vec::new(), &mut done))
 }
 
 #[cfg(test)]
 mod tests {
     use super::{Config, Signal};
-    use crate::program::{RestartPolicy, StartPolicy};
-    use std::path::Path;
+    use crate::program::{child::Status, RestartPolicy, StartPolicy};
+    use std::{
+        path::Path,
+        thread,
+        time::{Duration, Instant},
+    };
     const CONFIG: &str = "tests/tests.toml";
 
     #[test]
@@ -141,6 +667,16 @@ mod tests {
         assert_eq!(c.program[0].umask.unwrap(), 0o002);
     }
     #[test]
+    fn test_default_socket() {
+        let c = Config::load(CONFIG).unwrap();
+        assert_eq!(c.socket, super::default_socket());
+    }
+    #[test]
+    fn test_path() {
+        let c = Config::load(CONFIG).unwrap();
+        assert_eq!(c.path, Path::new(CONFIG));
+    }
+    #[test]
     fn test_random_name() {
         let c = Config::load(CONFIG).unwrap();
         assert!(!c.program[0].name.is_empty());
@@ -160,6 +696,180 @@ mod tests {
             .unwrap();
     }
     #[test]
+    fn empty_http_token() {
+        assert!(Config::load("tests/http_empty_token.toml")
+            .unwrap_err()
+            .to_string()
+            .contains("token can't be empty"));
+    }
+    #[test]
+    fn exit_code_of_first_failure() {
+        let mut c = Config::load("tests/groups.toml").unwrap();
+        let now = Instant::now();
+        for (program, status) in c.program.iter_mut().zip([
+            Status::Finished(now, 0),
+            Status::Finished(now, 3),
+            Status::Terminated(now - Duration::from_secs(1), 9),
+        ]) {
+            program.start().unwrap();
+            program.kill();
+            program.childs[0].status = status;
+            program.childs[0].stopped = false;
+        }
+        assert_eq!(c.exit_code(), 137);
+    }
+    #[test]
+    fn on_demand_without_sockets() {
+        assert!(Config::load("tests/on_demand_no_sockets.toml")
+            .unwrap_err()
+            .to_string()
+            .contains("needs sockets"));
+    }
+    #[test]
+    fn include() {
+        let c = Config::load("tests/include/taskmaster.toml").unwrap();
+        let names: Vec<_> = c.program.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["main", "a", "b", "b_second"]);
+    }
+    #[test]
+    fn duplicate_names() {
+        let error = Config::load("tests/duplicate_name.toml")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("`ls` is defined twice"), "{error}");
+        let error = Config::load("tests/include_duplicate.toml")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("tests/include_duplicate.toml and tests/include/conf.d/a.toml"),
+            "{error}"
+        );
+    }
+    #[test]
+    fn groups() {
+        let mut c = Config::load("tests/groups.toml").unwrap();
+        let mut names = |target: &str| {
+            c.find_programs(target)
+                .map(|programs| programs.iter().map(|p| p.name.clone()).collect::<Vec<_>>())
+        };
+        assert_eq!(names("web:*").unwrap(), ["web1", "web2"]);
+        assert_eq!(names("web:web2").unwrap(), ["web2"]);
+        assert_eq!(names("worker").unwrap(), ["worker"]);
+        assert!(names("web:worker").is_err());
+        assert!(names("workers:*").is_err());
+        let error = Config::load("tests/group_unknown_program.toml")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("group `web`: no program `web3`"), "{error}");
+    }
+    #[test]
+    fn depends_on() {
+        let mut c = Config::load("tests/depends_on.toml").unwrap();
+        c.start_program("web").unwrap();
+        assert!(c.program[0].waits_dependencies && c.program[0].childs.is_empty());
+        c.start_program("db").unwrap();
+        let start = Instant::now();
+        while c.program[0].childs.is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            c.program.iter_mut().for_each(|p| p.tick().unwrap());
+            c.start_waiting();
+            thread::sleep(Duration::from_millis(10));
+        }
+        // once db was running for min_runtime
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        c.quit();
+        assert!(matches!(c.program[1].childs[0].status, Status::Running(_)));
+        assert!(c.program[1].childs[0].stopped);
+        while !c.program.iter().all(|p| p.all_stopped()) {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            c.program.iter_mut().for_each(|p| p.tick().unwrap());
+            // stopped after web
+            if !c.program[0].all_stopped() {
+                assert!(matches!(c.program[1].childs[0].status, Status::Running(_)));
+            }
+            c.stop_next();
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+    #[test]
+    fn update_dependencies() {
+        let mut c = Config::load("tests/depends_on.toml").unwrap();
+        c.program.iter_mut().for_each(|p| p.start().unwrap());
+        let pid = |c: &Config, index: usize| c.program[index].childs[0].process.id();
+        let running = |c: &Config, index: usize| {
+            matches!(c.program[index].childs[0].status, Status::Running(_))
+        };
+        let (web, db) = (pid(&c, 0), pid(&c, 1));
+        let mut new = Config::load("tests/depends_on.toml").unwrap();
+        new.program[1].args = vec!["40".to_string()];
+        c.update(new).unwrap();
+        // web is restarted with db, which is stopped after it and started before it
+        assert_eq!(c.program_restarts, ["db", "web"]);
+        let start = Instant::now();
+        while pid(&c, 0) == web || !running(&c, 0) {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            c.program.iter_mut().for_each(|p| p.tick().unwrap());
+            let stopping = !matches!(
+                c.program[1].childs[0].status,
+                Status::Starting(_) | Status::Running(_)
+            );
+            if pid(&c, 1) == db && stopping {
+                assert!(c.program[0].all_stopped());
+            }
+            if pid(&c, 0) != web {
+                assert!(pid(&c, 1) != db && running(&c, 1));
+            }
+            c.restart_changed();
+            c.start_waiting();
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(c.program_restarts.is_empty());
+        c.program.iter_mut().for_each(|p| p.kill());
+    }
+    #[test]
+    fn dependency_errors() {
+        let error = Config::load("tests/depends_cycle.toml")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("a -> b -> c -> a"), "{error}");
+        let error = Config::load("tests/depends_unknown.toml")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("depends on `db`"), "{error}");
+    }
+    #[test]
+    fn supervisord() {
+        std::env::set_var("TASKMASTER_TEST_PORT", "80");
+        let c = Config::load("tests/supervisord.conf").unwrap();
+        assert_eq!(c.loglevel, tracing::Level::TRACE);
+        assert_eq!(c.socket, Path::new("/tmp/taskmaster-supervisord.sock"));
+        let web = &c.program[0];
+        assert_eq!(web.name, "web");
+        assert_eq!(web.cmd, Path::new("python3"));
+        assert_eq!(web.args, ["-m", "http.server", "8080"]);
+        assert_eq!(web.processes, 2);
+        assert_eq!(web.start_policy, StartPolicy::Manual);
+        assert_eq!(web.restart_policy, RestartPolicy::Always);
+        assert_eq!(web.min_runtime.as_secs(), 5);
+        assert_eq!(web.max_restarts, 7);
+        assert_eq!(web.valid_exit_codes, [0, 2]);
+        assert_eq!(web.stop_signal, Signal::SIGINT);
+        assert_eq!(web.graceful_timeout.as_secs(), 20);
+        assert_eq!(web.stdout, Some("/tmp/web.log".into()));
+        assert_eq!(web.stderr, web.stdout);
+        assert_eq!(web.env, ["MODE=prod", "GREETING=hello world"]);
+        assert_eq!(web.cwd, Some(Path::new("tests").canonicalize().unwrap()));
+        assert_eq!(web.umask, Some(0o022));
+        let alerts = &c.program[1];
+        assert_eq!(alerts.events, ["PROCESS_STATE_EXITED"]);
+        // the defaults of supervisord
+        assert_eq!(alerts.restart_policy, RestartPolicy::UnexpectedExit);
+        assert_eq!(alerts.min_runtime.as_secs(), 1);
+        assert_eq!(alerts.valid_exit_codes, [0]);
+        assert_eq!(c.group[0].name, "all");
+        assert_eq!(c.group[0].programs, ["web", "alerts"]);
+    }
+    #[test]
     #[should_panic]
     fn different_configs() {
         let base = Config::load("config/default.toml").unwrap();
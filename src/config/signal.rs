@@ -1,8 +1,8 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
 /// To get the associated signal number, cast the enum to u8: `*Signal::SIGHUP as u8`
-#[derive(Deserialize, Debug, PartialEq, Eq, Copy, Clone, Default)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Copy, Clone, Default)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum Signal {
     SIGHUP = 1,
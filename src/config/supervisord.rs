@@ -0,0 +1,558 @@
+//! supervisord configuration files, for migrations: the `[program:x]` sections are turned
+//! into the `[[program]]` of taskmaster, the `[group:x]` ones into its `[[group]]`, and the
+//! options it doesn't have are ignored with a warning.
+//!
+//! The file is converted to the same TOML table as a taskmaster configuration file, so the
+//! defaults and checks of the programs stay the same.
+
+use std::{env, error::Error, path::Path};
+use toml::{Table, Value};
+use tracing::{debug, warn};
+
+/// the options of `[program:x]` which have an equivalent in taskmaster
+pub const PROGRAM_OPTIONS: [&str; 19] = [
+    "command",
+    "numprocs",
+    "autostart",
+    "autorestart",
+    "startsecs",
+    "startretries",
+    "exitcodes",
+    "stopsignal",
+    "stopwaitsecs",
+    "stopasgroup",
+    "killasgroup",
+    "user",
+    "directory",
+    "umask",
+    "environment",
+    "stdout_logfile",
+    "stderr_logfile",
+    "redirect_stderr",
+    // for the event listeners only
+    "events",
+];
+
+/// the sections for the supervisorctl client and the XML-RPC interface, ignored
+pub const IGNORED_SECTIONS: [&str; 2] = ["supervisorctl", "rpcinterface"];
+
+/// the name of a section and the line of its header, and its `key = value` options with
+/// their line
+pub type Section = (String, usize, Vec<(String, String, usize)>);
+
+/// `supervisord.conf`, or any `.ini` file
+pub fn is_supervisord(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("conf" | "ini")
+    )
+}
+
+/// the taskmaster configuration described by a supervisord configuration file
+pub fn parse(raw: &str, path: &Path) -> Result<Table, Box<dyn Error>> {
+    let here = path
+        .canonicalize()
+        .ok()
+        .and_then(|p| p.parent().map(|d| d.display().to_string()))
+        .unwrap_or_default();
+    let mut config = Table::new();
+    let mut programs = Vec::new();
+    let mut groups = Vec::new();
+    for (section, _, options) in sections(raw)? {
+        let (kind, name) = section.split_once(':').unwrap_or((&section, ""));
+        let expand = |value: &str| expand(value, name, &here);
+        let options: Vec<_> = options
+            .into_iter()
+            .map(|(key, value, _)| (key, value))
+            .collect();
+        match kind {
+            "program" | "eventlistener" if !name.is_empty() => {
+                let options = options
+                    .into_iter()
+                    .filter(|(key, _)| {
+                        let supported = self::options(&section)
+                            .is_some_and(|options| options.contains(&key.as_str()));
+                        if !supported {
+                            unsupported(&section, key);
+                        }
+                        supported
+                    })
+                    .map(|(key, value)| Ok((key, expand(&value)?)))
+                    .collect::<Result<_, String>>()
+                    .map_err(|e| format!("[{section}]: {e}"))?;
+                let program = program(name, options).map_err(|e| format!("[{section}]: {e}"))?;
+                programs.push(Value::Table(program));
+            }
+            "group" if !name.is_empty() => {
+                for (key, value) in options {
+                    match key.as_str() {
+                        "programs" => {
+                            let members = value
+                                .split(',')
+                                .map(|p| Value::String(p.trim().to_string()))
+                                .collect();
+                            let group = Table::from_iter([
+                                ("name".to_string(), Value::String(name.to_string())),
+                                ("programs".to_string(), Value::Array(members)),
+                            ]);
+                            groups.push(Value::Table(group));
+                        }
+                        _ => unsupported(&section, &key),
+                    }
+                }
+            }
+            "supervisord" => {
+                for (key, value) in options {
+                    let value = expand(&value)?;
+                    match key.as_str() {
+                        "loglevel" => {
+                            config.insert(key, Value::String(loglevel(&value).to_string()));
+                        }
+                        "pidfile" => {
+                            config.insert(key, Value::String(value));
+                        }
+                        _ => unsupported(&section, &key),
+                    }
+                }
+            }
+            "unix_http_server" => {
+                for (key, value) in options {
+                    match key.as_str() {
+                        "file" => {
+                            config.insert("socket".to_string(), Value::String(expand(&value)?));
+                        }
+                        _ => unsupported(&section, &key),
+                    }
+                }
+            }
+            "include" => {
+                for (key, value) in options {
+                    match key.as_str() {
+                        "files" => {
+                            let globs = expand(&value)?
+                                .split_whitespace()
+                                .map(|g| Value::String(g.to_string()))
+                                .collect();
+                            config.insert("include".to_string(), Value::Array(globs));
+                        }
+                        _ => unsupported(&section, &key),
+                    }
+                }
+            }
+            _ if IGNORED_SECTIONS.contains(&kind) => {
+                debug!(section, "Ignoring supervisord section")
+            }
+            _ => warn!(section, "Unsupported supervisord section, ignored"),
+        }
+    }
+    config.insert("program".to_string(), Value::Array(programs));
+    config.insert("group".to_string(), Value::Array(groups));
+    Ok(config)
+}
+
+/// a `[program:x]` or `[eventlistener:x]` section, with the defaults of supervisord
+fn program(name: &str, options: Vec<(String, String)>) -> Result<Table, Box<dyn Error>> {
+    let mut program = Table::new();
+    program.insert("name".into(), name.into());
+    program.insert("restart_policy".into(), "unexpectedexit".into());
+    program.insert("min_runtime".into(), 1.into());
+    program.insert("valid_exit_codes".into(), Value::Array(vec![0.into()]));
+    let mut stdout = None;
+    let mut redirect_stderr = false;
+    for (key, value) in options {
+        match key.as_str() {
+            "command" => {
+                let mut words = split(&value)?.into_iter();
+                let command = words.next().ok_or("empty command")?;
+                program.insert("command".into(), command.into());
+                program.insert("args".into(), words.collect::<Vec<_>>().into());
+            }
+            "numprocs" => {
+                let processes: u8 = value
+                    .parse()
+                    .map_err(|_| format!("invalid numprocs `{value}`"))?;
+                program.insert("processes".into(), processes.into());
+            }
+            "autostart" => {
+                let policy = match boolean(&value)? {
+                    true => "auto",
+                    false => "manual",
+                };
+                program.insert("start_policy".into(), policy.into());
+            }
+            "autorestart" => {
+                let policy = match value.to_lowercase().as_str() {
+                    "unexpected" => "unexpectedexit",
+                    _ if boolean(&value)? => "always",
+                    _ => "never",
+                };
+                program.insert("restart_policy".into(), policy.into());
+            }
+            "startsecs" => {
+                program.insert("min_runtime".into(), number(&value)?);
+            }
+            "startretries" => {
+                program.insert("max_restarts".into(), number(&value)?);
+            }
+            "stopwaitsecs" => {
+                program.insert("graceful_timeout".into(), number(&value)?);
+            }
+            "exitcodes" => {
+                let codes = value
+                    .split(',')
+                    .map(|code| code.trim().parse::<i32>().map(Value::from))
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| format!("invalid exitcodes `{value}`"))?;
+                program.insert("valid_exit_codes".into(), Value::Array(codes));
+            }
+            "stopsignal" => {
+                let signal = value.trim().to_uppercase();
+                let signal = match signal.starts_with("SIG") {
+                    true => signal,
+                    false => format!("SIG{signal}"),
+                };
+                program.insert("stop_signal".into(), signal.into());
+            }
+            "stopasgroup" | "killasgroup" => {
+                program.insert(key, boolean(&value)?.into());
+            }
+            "user" => {
+                program.insert(key, value.into());
+            }
+            "directory" => {
+                program.insert("cwd".into(), value.into());
+            }
+            "umask" => {
+                let umask = u32::from_str_radix(&value, 8)
+                    .map_err(|_| format!("invalid umask `{value}`"))?;
+                program.insert(key, umask.into());
+            }
+            "environment" => {
+                program.insert("env".into(), environment(&value)?.into());
+            }
+            "stdout_logfile" => stdout = logfile(name, &key, value),
+            "stderr_logfile" => {
+                if let Some(path) = logfile(name, &key, value) {
+                    program.insert("stderr".into(), path.into());
+                }
+            }
+            "redirect_stderr" => redirect_stderr = boolean(&value)?,
+            "events" => {
+                let events: Vec<_> = value
+                    .split(',')
+                    .map(|e| e.trim().to_string())
+                    .filter(|e| !e.is_empty())
+                    .collect();
+                if events.iter().any(|e| !e.starts_with("PROCESS_STATE")) {
+                    warn!(
+                        name,
+                        events = value,
+                        "Only the PROCESS_STATE events are sent by taskmaster"
+                    );
+                }
+                program.insert(key, events.into());
+            }
+            _ => unreachable!("filtered by PROGRAM_OPTIONS"),
+        }
+    }
+    if !program.contains_key("command") {
+        return Err("missing command".into());
+    }
+    if let Some(stdout) = stdout {
+        if redirect_stderr {
+            program.insert("stderr".into(), stdout.clone().into());
+        }
+        program.insert("stdout".into(), stdout.into());
+    }
+    Ok(program)
+}
+
+fn unsupported(section: &str, key: &str) {
+    warn!(section, key, "Unsupported supervisord option, ignored");
+}
+
+/// the options taskmaster uses in a section, None for the sections it doesn't know
+pub fn options(section: &str) -> Option<&'static [&'static str]> {
+    let (kind, name) = section.split_once(':').unwrap_or((section, ""));
+    match kind {
+        // without `events`
+        "program" if !name.is_empty() => Some(&PROGRAM_OPTIONS[..PROGRAM_OPTIONS.len() - 1]),
+        "eventlistener" if !name.is_empty() => Some(&PROGRAM_OPTIONS),
+        "group" if !name.is_empty() => Some(&["programs"]),
+        "supervisord" => Some(&["loglevel", "pidfile"]),
+        "unix_http_server" => Some(&["file"]),
+        "include" => Some(&["files"]),
+        _ => None,
+    }
+}
+
+/// the sections of an INI file, with their options in order. like the python configparser,
+/// `;` and `#` start comments, and indented lines continue the value of the previous option
+pub fn sections(raw: &str) -> Result<Vec<Section>, String> {
+    let mut sections: Vec<Section> = Vec::new();
+    for (number, line) in raw.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with([';', '#']) {
+            continue;
+        }
+        let error = || format!("line {}: invalid line `{trimmed}`", number + 1);
+        if let Some(header) = trimmed.strip_prefix('[') {
+            let name = header.strip_suffix(']').ok_or_else(error)?;
+            sections.push((name.trim().to_string(), number + 1, Vec::new()));
+            continue;
+        }
+        let options = &mut sections.last_mut().ok_or_else(error)?.2;
+        let value = strip_comment(trimmed);
+        match options.last_mut() {
+            Some((_, previous, _)) if line.starts_with([' ', '\t']) => {
+                previous.push('\n');
+                previous.push_str(value);
+            }
+            _ => {
+                let delimiter = value.find(['=', ':']).ok_or_else(error)?;
+                let (key, value) = (&value[..delimiter], &value[delimiter + 1..]);
+                options.push((
+                    key.trim().to_lowercase(),
+                    value.trim().to_string(),
+                    number + 1,
+                ));
+            }
+        }
+    }
+    Ok(sections)
+}
+
+/// without the comment at the end of the line, which must follow a whitespace
+fn strip_comment(line: &str) -> &str {
+    let comment = line
+        .char_indices()
+        .zip(line.chars().skip(1))
+        .find(|((_, c), next)| c.is_whitespace() && matches!(next, ';' | '#'))
+        .map(|((i, _), _)| i);
+    match comment {
+        Some(i) => line[..i].trim_end(),
+        None => line,
+    }
+}
+
+/// the `%(name)s` expansions of supervisord: the program name, the directory of the file and
+/// the environment variables as `ENV_X`
+fn expand(value: &str, program: &str, here: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('%') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        if let Some(after) = rest.strip_prefix('%') {
+            // still escaped for taskmaster
+            expanded.push_str(if after.starts_with('(') { "%%" } else { "%" });
+            rest = after;
+            continue;
+        }
+        let invalid = || format!("invalid expansion in `{value}`");
+        let name_end = rest
+            .find(')')
+            .filter(|_| rest.starts_with('('))
+            .ok_or_else(invalid)?;
+        let name = &rest[1..name_end];
+        // the format, like `s` or `02d`
+        let format_end = rest[name_end + 1..]
+            .find(|c: char| c.is_ascii_alphabetic())
+            .ok_or_else(invalid)?;
+        match (name, name.strip_prefix("ENV_")) {
+            ("program_name" | "group_name", _) => expanded.push_str(program),
+            ("here", _) => expanded.push_str(here),
+            // expanded by taskmaster for each child
+            ("process_num", _) => {
+                expanded.push('%');
+                expanded.push_str(&rest[..name_end + format_end + 2]);
+            }
+            (_, Some(var)) => expanded.push_str(
+                &env::var(var).map_err(|e| format!("expanding `%({name})`: {var}: {e}"))?,
+            ),
+            _ => return Err(format!("unsupported expansion `%({name})` in `{value}`")),
+        }
+        rest = &rest[name_end + format_end + 2..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// split a command like a POSIX shell, without the expansions: quotes and backslashes
+fn split(command: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(format!("unterminated quote in `{command}`")),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some('\n') => (),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(format!("unterminated quote in `{command}`")),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(format!("unterminated quote in `{command}`")),
+                    }
+                }
+            }
+            '\\' => {
+                if let Some(c) = chars.next().filter(|&c| c != '\n') {
+                    word.get_or_insert_with(String::new).push(c);
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// `KEY="value",KEY2="value 2"` to `["KEY=value", "KEY2=value 2"]`
+fn environment(value: &str) -> Result<Vec<String>, String> {
+    let mut pairs = Vec::new();
+    let mut pair = String::new();
+    let mut quote = None;
+    // split on the commas outside of quotes
+    for c in value.chars().chain([',']) {
+        match (c, quote) {
+            (',', None) => {
+                let entry = pair.trim();
+                if !entry.is_empty() {
+                    let (key, value) = entry
+                        .split_once('=')
+                        .ok_or(format!("invalid environment `{entry}`"))?;
+                    pairs.push(format!("{}={}", key.trim(), split(value)?.join(" ")));
+                }
+                pair.clear();
+                continue;
+            }
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            _ => (),
+        }
+        pair.push(c);
+    }
+    match quote {
+        Some(_) => Err(format!("unterminated quote in `{value}`")),
+        None => Ok(pairs),
+    }
+}
+
+fn boolean(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(format!("invalid boolean `{value}`")),
+    }
+}
+
+fn number(value: &str) -> Result<Value, String> {
+    value
+        .parse::<u32>()
+        .map(Value::from)
+        .map_err(|_| format!("invalid number `{value}`"))
+}
+
+/// `AUTO` log files are kept by supervisord in its own directory, taskmaster has none
+fn logfile(name: &str, key: &str, value: String) -> Option<String> {
+    match value.as_str() {
+        "NONE" => None,
+        "AUTO" => {
+            warn!(
+                name,
+                key, "AUTO log files aren't supported, the output is discarded"
+            );
+            None
+        }
+        _ => Some(value),
+    }
+}
+
+fn loglevel(level: &str) -> &str {
+    match level.to_lowercase().as_str() {
+        "critical" | "error" => "error",
+        "warn" => "warn",
+        "debug" => "debug",
+        "trace" | "blather" => "trace",
+        _ => "info",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{environment, expand, sections, split, strip_comment};
+
+    #[test]
+    fn ini() {
+        let sections = sections(
+            "; comment\n[program:a]\ncommand = ls -l ; listing\nenvironment=A=1,\n  B=2\n\n[include]\nfiles: conf.d/*.conf",
+        )
+        .unwrap();
+        assert_eq!(sections.len(), 2);
+        assert_eq!((sections[0].0.as_str(), sections[0].1), ("program:a", 2));
+        assert_eq!(
+            sections[0].2,
+            [
+                ("command".to_string(), "ls -l".to_string(), 3),
+                ("environment".to_string(), "A=1,\nB=2".to_string(), 4)
+            ]
+        );
+        assert_eq!(sections[1].2[0].1, "conf.d/*.conf");
+        assert!(super::sections("command = ls").is_err());
+        assert_eq!(strip_comment("echo a;b # c"), "echo a;b");
+    }
+    #[test]
+    fn command() {
+        assert_eq!(
+            split(r#"sh -c "echo \"hi\" there" 'a b'\ c"#).unwrap(),
+            ["sh", "-c", "echo \"hi\" there", "a b c"]
+        );
+        assert!(split("sh -c 'echo").is_err());
+    }
+    #[test]
+    fn env() {
+        assert_eq!(
+            environment(r#"A="1,2",B='x y', C=3"#).unwrap(),
+            ["A=1,2", "B=x y", "C=3"]
+        );
+        assert!(environment("A").is_err());
+    }
+    #[test]
+    fn expansions() {
+        std::env::set_var("TASKMASTER_TEST_EXPAND", "value");
+        assert_eq!(
+            expand(
+                "%(program_name)s %(here)s/x %(ENV_TASKMASTER_TEST_EXPAND)s 100%%",
+                "web",
+                "/etc"
+            )
+            .unwrap(),
+            "web /etc/x value 100%"
+        );
+        assert_eq!(
+            expand("%(process_num)02d %%(here)s", "web", "/etc").unwrap(),
+            "%(process_num)02d %%(here)s"
+        );
+        assert!(expand("%(host_node_name)s", "web", "/etc").is_err());
+    }
+}
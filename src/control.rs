@@ -0,0 +1,328 @@
+use crate::tui::Command;
+use std::{
+    error::Error,
+    fs,
+    io::{self, ErrorKind, Read, Write},
+    mem,
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tracing::{debug, trace, warn};
+
+/// a client that didn't send a full line, or didn't read its reply, after this is dropped
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Unix domain socket on which `taskmasterctl` sends its commands.
+///
+/// The protocol is line based: the client sends a single command line, using the same syntax
+/// as the tui shell, and receives `ok` or `error` on the first line, followed by the message.
+#[derive(Debug)]
+pub struct ControlSocket {
+    path: PathBuf,
+    listener: UnixListener,
+    clients: Vec<Client>,
+    replies: Vec<Reply>,
+}
+
+#[derive(Debug)]
+struct Client {
+    stream: UnixStream,
+    buffer: Vec<u8>,
+    since: Instant,
+}
+
+/// A reply which the client didn't read entirely yet.
+#[derive(Debug)]
+struct Reply {
+    stream: UnixStream,
+    response: Vec<u8>,
+    written: usize,
+    since: Instant,
+}
+
+/// A command received on the control socket, waiting for its reply.
+#[derive(Debug)]
+pub struct Request {
+    stream: UnixStream,
+    pub line: String,
+}
+
+impl ControlSocket {
+    /// bind the socket, replacing a stale socket file if no taskmaster is listening on it anymore
+    pub fn bind(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(format!(
+                    "another taskmaster is already listening on {}",
+                    path.display()
+                )
+                .into());
+            }
+            debug!(path = %path.display(), "Removing stale control socket");
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        debug!(path = %path.display(), "Control socket listening");
+        Ok(Self {
+            path: path.to_path_buf(),
+            listener,
+            clients: Vec::new(),
+            replies: Vec::new(),
+        })
+    }
+
+    /// send what the clients can read of their replies, accept the new clients and return the
+    /// ones which sent a complete command line
+    pub fn poll(&mut self) -> Vec<Request> {
+        for mut reply in mem::take(&mut self.replies) {
+            match reply.write() {
+                Ok(true) => {}
+                Ok(false) if reply.since.elapsed() < CLIENT_TIMEOUT => self.replies.push(reply),
+                Ok(false) => debug!("Control client timed out reading its reply"),
+                Err(e) => debug!(error = %e, "replying to control client"),
+            }
+        }
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        warn!(error = %e, "configuring control client");
+                        continue;
+                    }
+                    trace!("New control client");
+                    self.clients.push(Client {
+                        stream,
+                        buffer: Vec::new(),
+                        since: Instant::now(),
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!(error = %e, "accepting control client");
+                    break;
+                }
+            }
+        }
+        let mut requests = Vec::new();
+        for mut client in mem::take(&mut self.clients) {
+            match client.read_line() {
+                Ok(Some(line)) => requests.push(Request {
+                    stream: client.stream,
+                    line,
+                }),
+                Ok(None) if client.since.elapsed() < CLIENT_TIMEOUT => self.clients.push(client),
+                Ok(None) => debug!("Control client timed out"),
+                Err(e) => debug!(error = %e, "Dropping control client"),
+            }
+        }
+        requests
+    }
+
+    /// the file descriptors to wait on before calling poll again
+    pub fn fds(&self) -> Vec<RawFd> {
+        let clients = self.clients.iter().map(|c| c.stream.as_raw_fd());
+        [self.listener.as_raw_fd()]
+            .into_iter()
+            .chain(clients)
+            .collect()
+    }
+
+    /// the file descriptors to wait on being writable before calling poll again
+    pub fn write_fds(&self) -> Vec<RawFd> {
+        self.replies.iter().map(|r| r.stream.as_raw_fd()).collect()
+    }
+
+    /// when the oldest client times out
+    pub fn deadline(&self) -> Option<Instant> {
+        let clients = self.clients.iter().map(|c| c.since);
+        let replies = self.replies.iter().map(|r| r.since);
+        clients
+            .chain(replies)
+            .map(|since| since + CLIENT_TIMEOUT)
+            .min()
+    }
+
+    /// send the result of the command to the client, and close the connection once it's sent;
+    /// what the client doesn't read right away is sent by the next polls
+    pub fn reply(&mut self, request: Request, result: Result<String, String>) {
+        let response = match result {
+            Ok(message) => format!("ok\n{message}\n"),
+            Err(message) => format!("error\n{message}\n"),
+        };
+        let mut reply = Reply {
+            stream: request.stream,
+            response: response.into_bytes(),
+            written: 0,
+            since: Instant::now(),
+        };
+        match reply.write() {
+            Ok(true) => {}
+            Ok(false) => self.replies.push(reply),
+            Err(e) => debug!(error = %e, "replying to control client"),
+        }
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Client {
+    /// read what is available, and return the first line once it's complete
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut buf = [0; 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) if self.buffer.is_empty() => {
+                    return Err(io::Error::new(ErrorKind::UnexpectedEof, "closed"))
+                }
+                Ok(0) => break,
+                Ok(n) => self.buffer.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    if !self.buffer.contains(&b'\n') {
+                        return Ok(None);
+                    }
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let line = self
+            .buffer
+            .split(|&b| b == b'\n')
+            .next()
+            .unwrap_or_default();
+        Ok(Some(String::from_utf8_lossy(line).trim().to_string()))
+    }
+}
+
+impl Request {
+    /// parse the received line as a command
+    pub fn command(&self) -> Result<Command, String> {
+        self.line
+            .parse()
+            .map_err(|_| format!("invalid command `{}`, usage: {}", self.line, Command::HELP))
+    }
+}
+
+impl Reply {
+    /// write what the client can read, and return whether the whole reply is sent
+    fn write(&mut self) -> io::Result<bool> {
+        while self.written < self.response.len() {
+            match self.stream.write(&self.response[self.written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => self.written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ControlSocket;
+    use std::{
+        io::{Read, Write},
+        os::unix::net::UnixStream,
+        thread::{self, sleep},
+        time::{Duration, Instant},
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn request_reply() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        let mut socket = ControlSocket::bind(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"status ls\n").unwrap();
+        let mut requests = Vec::new();
+        for _ in 0..100 {
+            requests = socket.poll();
+            if !requests.is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        assert_eq!(requests.len(), 1);
+        let request = requests.pop().unwrap();
+        assert_eq!(request.line, "status ls");
+        assert!(request.command().is_ok());
+        socket.reply(request, Ok("all good".to_string()));
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert_eq!(response, "ok\nall good\n");
+    }
+
+    #[test]
+    fn slow_client() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        let mut socket = ControlSocket::bind(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"status\n").unwrap();
+        sleep(Duration::from_millis(50));
+        let request = socket.poll().pop().unwrap();
+        // more than the socket buffer, with the client not reading yet
+        let message = "x".repeat(4 << 20);
+        let start = Instant::now();
+        socket.reply(request, Ok(message.clone()));
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert_eq!(socket.write_fds().len(), 1);
+        assert!(socket.deadline().is_some());
+        let reader = thread::spawn(move || {
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            response
+        });
+        for _ in 0..500 {
+            socket.poll();
+            if socket.write_fds().is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        assert!(socket.write_fds().is_empty());
+        assert_eq!(reader.join().unwrap(), format!("ok\n{message}\n"));
+    }
+
+    #[test]
+    fn invalid_command() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        let mut socket = ControlSocket::bind(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"dance\n").unwrap();
+        sleep(Duration::from_millis(50));
+        let request = socket.poll().pop().unwrap();
+        assert!(request.command().is_err());
+    }
+
+    #[test]
+    fn already_listening() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        let _socket = ControlSocket::bind(&path).unwrap();
+        assert!(ControlSocket::bind(&path).is_err());
+    }
+
+    #[test]
+    fn stale_socket() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        ControlSocket::bind(&path).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    env,
+    error::Error,
+    fs::{self, File},
+    io::{self, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    process,
+};
+use tracing::{debug, warn};
+
+/// Exclusive locks held for the whole life of taskmaster, so a second instance can't supervise
+/// the same configuration file or listen on the same control socket.
+#[derive(Debug)]
+pub struct InstanceLock {
+    _files: Vec<File>,
+}
+
+impl InstanceLock {
+    pub fn acquire(
+        config: impl AsRef<Path>,
+        socket: impl AsRef<Path>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let config = fs::canonicalize(config.as_ref())
+            .map_err(|e| format!("resolving {}: {e}", config.as_ref().display()))?;
+        let config_lock = env::temp_dir().join(format!(
+            "taskmaster-{}.lock",
+            config.display().to_string().replace('/', "%")
+        ));
+        let mut socket_lock = socket.as_ref().as_os_str().to_owned();
+        socket_lock.push(".lock");
+
+        let files = vec![
+            lock(&config_lock).map_err(|e| {
+                format!(
+                    "taskmaster is already running with the configuration file {}: {e}",
+                    config.display()
+                )
+            })?,
+            lock(Path::new(&socket_lock)).map_err(|e| {
+                format!(
+                    "taskmaster is already running with the control socket {}: {e}",
+                    socket.as_ref().display()
+                )
+            })?,
+        ];
+        Ok(Self { _files: files })
+    }
+}
+
+/// take an exclusive, non blocking lock on the file. released when the file is closed
+fn lock(path: &Path) -> Result<File, Box<dyn Error>> {
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(|e| format!("opening the lock file {}: {e}", path.display()))?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() == io::ErrorKind::WouldBlock {
+            return Err(format!("{} is locked", path.display()).into());
+        }
+        return Err(format!("locking {}: {e}", path.display()).into());
+    }
+    debug!(path = %path.display(), "Lock acquired");
+    Ok(file)
+}
+
+/// Detach from the terminal: double fork, new session, and stdio redirected to /dev/null.
+///
+/// Only the final daemon process returns, the intermediate ones exit without running destructors.
+/// The current directory is kept, so the relative paths of the configuration stay valid.
+pub fn daemonize() -> Result<(), Box<dyn Error>> {
+    fork_and_exit_parent()?;
+    if unsafe { libc::setsid() } == -1 {
+        return Err(format!("setsid: {}", io::Error::last_os_error()).into());
+    }
+    fork_and_exit_parent()?;
+    let null = File::options().read(true).write(true).open("/dev/null")?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(format!("redirecting stdio: {}", io::Error::last_os_error()).into());
+        }
+    }
+    debug!(pid = process::id(), "Daemonized");
+    Ok(())
+}
+
+fn fork_and_exit_parent() -> Result<(), Box<dyn Error>> {
+    match unsafe { libc::fork() } {
+        -1 => Err(format!("fork: {}", io::Error::last_os_error()).into()),
+        0 => Ok(()),
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+/// File containing the pid of taskmaster, removed when dropped.
+#[derive(Debug)]
+pub struct Pidfile {
+    path: PathBuf,
+}
+
+impl Pidfile {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = File::create(&path)
+            .map_err(|e| format!("creating the pidfile {}: {e}", path.display()))?;
+        writeln!(file, "{}", process::id())?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!(path = %self.path.display(), error = %e, "removing the pidfile");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InstanceLock, Pidfile};
+    use std::{fs, process};
+    use tempfile::tempdir;
+
+    #[test]
+    fn single_instance() {
+        let dir = tempdir().unwrap();
+        let config = dir.path().join("single_instance.toml");
+        fs::write(&config, "").unwrap();
+        let socket = dir.path().join("first.sock");
+        let lock = InstanceLock::acquire(&config, &socket).unwrap();
+        let err = InstanceLock::acquire(&config, dir.path().join("second.sock")).unwrap_err();
+        assert!(err.to_string().contains("configuration file"));
+        drop(lock);
+        InstanceLock::acquire(&config, &socket).unwrap();
+    }
+    #[test]
+    fn same_socket() {
+        let dir = tempdir().unwrap();
+        let first = dir.path().join("first.toml");
+        let second = dir.path().join("second.toml");
+        fs::write(&first, "").unwrap();
+        fs::write(&second, "").unwrap();
+        let socket = dir.path().join("taskmaster.sock");
+        let _lock = InstanceLock::acquire(&first, &socket).unwrap();
+        let err = InstanceLock::acquire(&second, &socket).unwrap_err();
+        assert!(err.to_string().contains("control socket"));
+    }
+    #[test]
+    fn pidfile() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("taskmaster.pid");
+        let pidfile = Pidfile::create(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", process::id())
+        );
+        drop(pidfile);
+        assert!(!path.exists());
+    }
+}
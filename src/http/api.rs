@@ -0,0 +1,334 @@
+use super::{Request, Response};
+use crate::{
+    config::Config,
+    program::{
+        child::{Child, Status},
+        Program,
+    },
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{error, info};
+
+/// body of `POST /reload`, every field is optional
+#[derive(Deserialize, Debug, Default)]
+struct ReloadBody {
+    path: Option<String>,
+}
+
+/// Route a request of the JSON API:
+/// - `GET /programs` and `GET /programs/<name>`: configuration and status of the programs
+/// - `POST /programs/<name>/start`, `.../stop` and `.../restart`
+/// - `POST /reload`, with an optional `{"path": "..."}` body
+pub fn handle(config: &mut Config, request: &Request) -> Response {
+    let path = request.path.split('?').next().unwrap_or_default();
+    let segments = path
+        .trim_matches('/')
+        .split('/')
+        .map(percent_decode)
+        .collect::<Vec<_>>();
+    let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["programs"]) => Response::json(
+            200,
+            Value::Array(config.program.iter().map(program_json).collect()),
+        ),
+        ("GET", ["programs", name]) => match find(config, name) {
+            Ok(program) => Response::json(200, program_json(program)),
+            Err(response) => response,
+        },
+        ("POST", ["programs", name, action]) => {
+            let program = match find(config, name) {
+                Ok(program) => program,
+                Err(response) => return response,
+            };
+            match *action {
+                "start" => {
+                    info!(name, "Starting");
+                    // once its dependencies are running
+                    if let Err(e) = config.start_program(name) {
+                        return error_json(409, e.to_string());
+                    }
+                    match find(config, name) {
+                        Ok(program) => Response::json(200, program_json(program)),
+                        Err(response) => response,
+                    }
+                }
+                "stop" => {
+                    info!(name, "Stopping");
+                    program.stop();
+                    Response::json(200, program_json(program))
+                }
+                "restart" => {
+                    info!(name, "Restarting");
+                    program.restart();
+                    Response::json(200, program_json(program))
+                }
+                _ => error_json(404, format!("unknown action `{action}`")),
+            }
+        }
+        ("POST", ["reload"]) => {
+            let body = match request.body.is_empty() {
+                true => ReloadBody::default(),
+                false => match serde_json::from_slice::<ReloadBody>(&request.body) {
+                    Ok(body) => body,
+                    Err(e) => return error_json(400, format!("invalid body: {e}")),
+                },
+            };
+            let path = body
+                .path
+                .unwrap_or_else(|| config.path.display().to_string());
+            let result = Config::load(&path).and_then(|new| config.update(new));
+            match result {
+                Ok(()) => Response::json(200, json!({ "reloaded": path })),
+                Err(e) => {
+                    error!(path, error = e, "reloading the configuration file");
+                    error_json(400, format!("reloading {path}: {e}"))
+                }
+            }
+        }
+        (_, ["programs", ..] | ["reload"]) => error_json(405, "method not allowed"),
+        _ => error_json(404, "not found"),
+    }
+}
+
+fn find<'a>(config: &'a mut Config, name: &str) -> Result<&'a mut Program, Response> {
+    config
+        .program
+        .iter_mut()
+        .find(|p| p.name == name)
+        .ok_or_else(|| error_json(404, format!("program `{name}` not found")))
+}
+
+pub fn error_json(status: u16, message: impl Into<String>) -> Response {
+    Response::json(status, json!({ "error": message.into() }))
+}
+
+fn program_json(program: &Program) -> Value {
+    let mut value = serde_json::to_value(program).unwrap_or_default();
+    value["childs"] = program.childs.iter().map(child_json).collect();
+    value
+}
+
+fn child_json(child: &Child) -> Value {
+    let mut value = json!({
+        "pid": child.process.id(),
+        "restarts": child.restarts,
+        "since": child.status.get_instant().elapsed().as_secs_f64(),
+    });
+    let (status, code, signal) = match child.status {
+        Status::Stopped(_) => ("stopped", None, None),
+        Status::Starting(_) => ("starting", None, None),
+        Status::Running(_) => ("running", None, None),
+        Status::Terminating(_) => ("terminating", None, None),
+        Status::Finished(_, code) => ("finished", Some(code), None),
+        Status::Terminated(_, signal) => ("terminated", None, Some(signal)),
+    };
+    value["status"] = status.into();
+    if let Some(code) = code {
+        value["exit_code"] = code.into();
+    }
+    if let Some(signal) = signal {
+        value["signal"] = signal.into();
+    }
+    value
+}
+
+/// decode the `%XX` escapes of an url path segment
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::percent_decode;
+    use crate::{
+        config::Config,
+        http::{HttpConfig, HttpServer, Request, Stream},
+    };
+    use serde_json::Value;
+    use std::os::unix::net::UnixStream;
+    use tempfile::{tempdir, TempDir};
+
+    fn server() -> (TempDir, HttpServer) {
+        let dir = tempdir().unwrap();
+        let server = HttpServer::bind(&HttpConfig {
+            listen: format!("unix:{}", dir.path().join("http.sock").display()),
+            token: "secret".to_string(),
+        })
+        .unwrap();
+        (dir, server)
+    }
+
+    /// send a request through the server, and return the status and json body of the response
+    fn send(
+        server: &HttpServer,
+        config: &mut Config,
+        method: &str,
+        path: &str,
+        authorization: Option<&str>,
+        body: &str,
+    ) -> (u16, Value) {
+        let (stream, _) = UnixStream::pair().unwrap();
+        let request = Request {
+            stream: Stream::Unix(stream),
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: authorization
+                .map(|a| ("Authorization".to_string(), a.to_string()))
+                .into_iter()
+                .collect(),
+            body: body.as_bytes().to_vec(),
+        };
+        let response = server.handle(config, &request);
+        assert_eq!(response.content_type, "application/json");
+        (
+            response.status,
+            serde_json::from_slice(&response.body).unwrap(),
+        )
+    }
+
+    fn call(server: &HttpServer, config: &mut Config, method: &str, path: &str) -> (u16, Value) {
+        send(server, config, method, path, Some("Bearer secret"), "")
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(percent_decode("never%20ending"), "never ending");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("plain"), "plain");
+    }
+    #[test]
+    fn status() {
+        let (_dir, server) = server();
+        let mut config = Config::load("tests/depends_on.toml").unwrap();
+        let (status, body) = call(&server, &mut config, "GET", "/programs");
+        assert_eq!(status, 200);
+        let names: Vec<_> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| &p["name"])
+            .collect();
+        assert_eq!(names, ["web", "db"]);
+        let (status, body) = call(&server, &mut config, "GET", "/programs/db?verbose");
+        assert_eq!(status, 200);
+        assert_eq!(body["name"], "db");
+        assert_eq!(body["childs"], Value::Array(Vec::new()));
+    }
+    #[test]
+    fn start_stop_restart() {
+        let (_dir, server) = server();
+        let mut config = Config::load("tests/depends_on.toml").unwrap();
+        // waits for db to be running
+        let (status, body) = call(&server, &mut config, "POST", "/programs/web/start");
+        assert_eq!(status, 200);
+        assert_eq!(body["childs"].as_array().unwrap().len(), 0);
+        assert!(config.program[0].waits_dependencies);
+
+        let (status, body) = call(&server, &mut config, "POST", "/programs/db/start");
+        assert_eq!(status, 200);
+        assert_eq!(body["childs"][0]["status"], "starting");
+        let pid = body["childs"][0]["pid"].as_u64().unwrap();
+        assert_eq!(pid, config.program[1].childs[0].process.id() as u64);
+
+        let (status, body) = call(&server, &mut config, "POST", "/programs/db/stop");
+        assert_eq!(status, 200);
+        assert_eq!(body["childs"][0]["status"], "terminating");
+        assert!(config.program[1].stopped);
+
+        let (status, body) = call(&server, &mut config, "POST", "/programs/db/restart");
+        assert_eq!(status, 200);
+        assert_eq!(body["childs"][0]["pid"], pid);
+        assert!(config.program[1].force_restart);
+        assert!(!config.program[1].stopped);
+        config.program[1].kill();
+    }
+    #[test]
+    fn not_found() {
+        let (_dir, server) = server();
+        let mut config = Config::load("tests/depends_on.toml").unwrap();
+        for (method, path) in [
+            ("GET", "/programs/nope"),
+            ("POST", "/programs/nope/start"),
+            ("POST", "/programs/db/dance"),
+            ("GET", "/nowhere"),
+        ] {
+            let (status, body) = call(&server, &mut config, method, path);
+            assert_eq!(status, 404, "{method} {path}");
+            assert!(body["error"].is_string());
+        }
+        let (status, body) = call(&server, &mut config, "GET", "/programs/never%20ending");
+        assert_eq!(status, 404);
+        assert_eq!(body["error"], "program `never ending` not found");
+        for (method, path) in [("DELETE", "/programs"), ("GET", "/programs/db/start")] {
+            let (status, _) = call(&server, &mut config, method, path);
+            assert_eq!(status, 405, "{method} {path}");
+        }
+        assert!(config.program.iter().all(|p| p.childs.is_empty()));
+    }
+    #[test]
+    fn unauthorized() {
+        let (_dir, server) = server();
+        let mut config = Config::load("tests/depends_on.toml").unwrap();
+        for authorization in [
+            None,
+            Some("Bearer wrong"),
+            Some("Bearer "),
+            Some("secret"),
+            // user:wrong
+            Some("Basic dXNlcjp3cm9uZw=="),
+        ] {
+            let (status, body) = send(
+                &server,
+                &mut config,
+                "POST",
+                "/programs/db/start",
+                authorization,
+                "",
+            );
+            assert_eq!(status, 401, "{authorization:?}");
+            assert_eq!(body["error"], "invalid or missing token");
+        }
+        assert!(config.program[1].childs.is_empty());
+        // user:secret
+        let basic = Some("Basic dXNlcjpzZWNyZXQ=");
+        let (status, _) = send(&server, &mut config, "GET", "/programs", basic, "");
+        assert_eq!(status, 200);
+    }
+    #[test]
+    fn reload_errors() {
+        let (_dir, server) = server();
+        let mut config = Config::load("tests/depends_on.toml").unwrap();
+        let token = Some("Bearer secret");
+        let (status, body) = send(&server, &mut config, "POST", "/reload", token, "{");
+        assert_eq!(status, 400);
+        assert!(body["error"].as_str().unwrap().starts_with("invalid body"));
+        let missing = r#"{"path": "tests/missing.toml"}"#;
+        let (status, _) = send(&server, &mut config, "POST", "/reload", token, missing);
+        assert_eq!(status, 400);
+        assert_eq!(config.program.len(), 2);
+        let (status, body) = send(&server, &mut config, "POST", "/reload", token, "");
+        assert_eq!(status, 200);
+        assert_eq!(body["reloaded"], "tests/depends_on.toml");
+    }
+}
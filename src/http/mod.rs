@@ -0,0 +1,486 @@
+pub mod api;
+pub mod supervisor;
+pub mod xmlrpc;
+
+use crate::config::Config;
+use serde::Deserialize;
+use std::{
+    error::Error,
+    fs,
+    io::{self, ErrorKind, Read, Write},
+    mem,
+    net::{SocketAddr, TcpListener, TcpStream},
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use tracing::{debug, trace, warn};
+
+/// a client that didn't send a full request after this is dropped
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HttpConfig {
+    /// `127.0.0.1:9001` style loopback address, or `unix:/path/to/socket`
+    pub listen: String,
+    /// expected in the `Authorization: Bearer <token>` header of every request
+    pub token: String,
+}
+
+#[derive(Debug)]
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+#[derive(Debug)]
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+/// Minimal HTTP/1.1 server, polled from the main loop like the control socket.
+///
+/// Every connection carries a single request and is closed after the response.
+#[derive(Debug)]
+pub struct HttpServer {
+    listener: Listener,
+    token: String,
+    clients: Vec<Client>,
+}
+
+#[derive(Debug)]
+struct Client {
+    stream: Stream,
+    buffer: Vec<u8>,
+    since: Instant,
+}
+
+#[derive(Debug)]
+pub struct Request {
+    stream: Stream,
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl HttpServer {
+    pub fn bind(config: &HttpConfig) -> Result<Self, Box<dyn Error>> {
+        let listener = if let Some(path) = config.listen.strip_prefix("unix:") {
+            let path = PathBuf::from(path);
+            if path.exists() {
+                if UnixStream::connect(&path).is_ok() {
+                    return Err(format!("{} is already in use", path.display()).into());
+                }
+                fs::remove_file(&path)?;
+            }
+            let listener = UnixListener::bind(&path)?;
+            listener.set_nonblocking(true)?;
+            Listener::Unix(listener, path)
+        } else {
+            let address: SocketAddr = config
+                .listen
+                .parse()
+                .map_err(|e| format!("invalid address `{}`: {e}", config.listen))?;
+            if !address.ip().is_loopback() {
+                return Err(format!(
+                    "refusing to listen on {address}, only loopback addresses are allowed"
+                )
+                .into());
+            }
+            let listener = TcpListener::bind(address)?;
+            listener.set_nonblocking(true)?;
+            Listener::Tcp(listener)
+        };
+        debug!(listen = config.listen, "HTTP server listening");
+        Ok(Self {
+            listener,
+            token: config.token.clone(),
+            clients: Vec::new(),
+        })
+    }
+
+    /// accept the new clients and return the complete requests
+    pub fn poll(&mut self) -> Vec<Request> {
+        loop {
+            let accepted = match &self.listener {
+                Listener::Tcp(l) => l.accept().map(|(s, _)| Stream::Tcp(s)),
+                Listener::Unix(l, _) => l.accept().map(|(s, _)| Stream::Unix(s)),
+            };
+            match accepted {
+                Ok(stream) => {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        warn!(error = %e, "configuring HTTP client");
+                        continue;
+                    }
+                    trace!("New HTTP client");
+                    self.clients.push(Client {
+                        stream,
+                        buffer: Vec::new(),
+                        since: Instant::now(),
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!(error = %e, "accepting HTTP client");
+                    break;
+                }
+            }
+        }
+        let mut requests = Vec::new();
+        for mut client in mem::take(&mut self.clients) {
+            match client.read_request() {
+                Ok(Some(((method, path, headers), body))) => requests.push(Request {
+                    stream: client.stream,
+                    method,
+                    path,
+                    headers,
+                    body,
+                }),
+                Ok(None) if client.since.elapsed() < CLIENT_TIMEOUT => self.clients.push(client),
+                Ok(None) => debug!("HTTP client timed out"),
+                Err(e) => debug!(error = %e, "Dropping HTTP client"),
+            }
+        }
+        requests
+    }
+
+    /// the file descriptors to wait on before calling poll again
+    pub fn fds(&self) -> Vec<RawFd> {
+        let listener = match &self.listener {
+            Listener::Tcp(l) => l.as_raw_fd(),
+            Listener::Unix(l, _) => l.as_raw_fd(),
+        };
+        let clients = self.clients.iter().map(|c| match &c.stream {
+            Stream::Tcp(s) => s.as_raw_fd(),
+            Stream::Unix(s) => s.as_raw_fd(),
+        });
+        [listener].into_iter().chain(clients).collect()
+    }
+
+    /// when the oldest client times out
+    pub fn deadline(&self) -> Option<Instant> {
+        self.clients.iter().map(|c| c.since + CLIENT_TIMEOUT).min()
+    }
+
+    /// check the authentication of the request, and route it
+    pub fn handle(&self, config: &mut Config, request: &Request) -> Response {
+        if !self.is_authorized(request) {
+            debug!(path = request.path, "Unauthorized HTTP request");
+            return api::error_json(401, "invalid or missing token");
+        }
+        match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/RPC2") => supervisor::handle(config, &request.body),
+            _ => api::handle(config, request),
+        }
+    }
+
+    /// check the bearer token of the request. supervisord clients use basic authentication,
+    /// for them the token is the password and the username is ignored
+    fn is_authorized(&self, request: &Request) -> bool {
+        let Some(authorization) = request.header("authorization") else {
+            return false;
+        };
+        if let Some(token) = authorization.strip_prefix("Bearer ") {
+            return constant_time_eq(token.trim(), &self.token);
+        }
+        authorization
+            .strip_prefix("Basic ")
+            .and_then(|credentials| base64_decode(credentials.trim()))
+            .and_then(|credentials| String::from_utf8(credentials).ok())
+            .is_some_and(|credentials| {
+                credentials
+                    .split_once(':')
+                    .is_some_and(|(_, password)| constant_time_eq(password, &self.token))
+            })
+    }
+}
+
+/// compare without stopping at the first difference, so the response time doesn't tell how
+/// much of the token is right
+fn constant_time_eq(given: &str, expected: &str) -> bool {
+    let (given, expected) = (given.as_bytes(), expected.as_bytes());
+    let mut difference = given.len() ^ expected.len();
+    for (i, byte) in given.iter().enumerate() {
+        difference |= (byte ^ expected.get(i).copied().unwrap_or_default()) as usize;
+    }
+    difference == 0
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = &self.listener {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+impl Client {
+    /// read what is available, and parse the request once the headers and body are complete
+    fn read_request(&mut self) -> io::Result<Option<(Head, Vec<u8>)>> {
+        let mut buf = [0; 4096];
+        let mut closed = false;
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(n) => self.buffer.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+            if self.buffer.len() > MAX_REQUEST_SIZE {
+                return Err(io::Error::new(ErrorKind::InvalidData, "request too large"));
+            }
+        }
+        let incomplete = || match closed {
+            true => Err(io::Error::new(ErrorKind::UnexpectedEof, "closed")),
+            false => Ok(None),
+        };
+        let Some((head, body)) = parse_head(&self.buffer)? else {
+            return incomplete();
+        };
+        let length = head
+            .2
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+            .map(|(_, v)| v.parse::<usize>())
+            .transpose()
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "invalid content-length"))?
+            .unwrap_or(0);
+        if body.len() < length {
+            return incomplete();
+        }
+        let body = body[..length].to_vec();
+        Ok(Some((head, body)))
+    }
+}
+
+type Head = (String, String, Vec<(String, String)>);
+
+/// split the request line and headers from the body, if all the headers have been received
+fn parse_head(buffer: &[u8]) -> io::Result<Option<(Head, &[u8])>> {
+    let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Ok(None);
+    };
+    let invalid = |msg: &str| io::Error::new(ErrorKind::InvalidData, msg.to_string());
+    let head = std::str::from_utf8(&buffer[..end]).map_err(|_| invalid("invalid utf-8"))?;
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().ok_or(invalid("missing method"))?;
+    let path = request_line.next().ok_or(invalid("missing path"))?;
+    let headers = lines
+        .map(|line| {
+            line.split_once(':')
+                .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+                .ok_or(invalid("invalid header"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Some((
+        (method.to_string(), path.to_string(), headers),
+        &buffer[end + 4..],
+    )))
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// send the response, and close the connection
+    pub fn reply(mut self, response: Response) {
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            response.status,
+            reason(response.status),
+            response.content_type,
+            response.body.len()
+        );
+        let sent = self
+            .stream
+            .set_nonblocking(false)
+            .and_then(|_| self.stream.set_write_timeout(Some(Duration::from_secs(1))))
+            .and_then(|_| self.stream.write_all(head.as_bytes()))
+            .and_then(|_| self.stream.write_all(&response.body));
+        if let Err(e) = sent {
+            debug!(error = %e, "replying to HTTP client");
+        }
+    }
+}
+
+impl Response {
+    pub fn json(status: u16, value: serde_json::Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: value.to_string().into_bytes(),
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Internal Server Error",
+    }
+}
+
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let mut bits = 0u32;
+    let mut count = 0;
+    let mut decoded = Vec::with_capacity(input.len() * 3 / 4);
+    for byte in input.trim_end_matches('=').bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+impl Stream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.set_nonblocking(nonblocking),
+            Stream::Unix(s) => s.set_nonblocking(nonblocking),
+        }
+    }
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.set_write_timeout(timeout),
+            Stream::Unix(s) => s.set_write_timeout(timeout),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.read(buf),
+            Stream::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.write(buf),
+            Stream::Unix(s) => s.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.flush(),
+            Stream::Unix(s) => s.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base64_decode, constant_time_eq, parse_head, HttpConfig, HttpServer, Response};
+    use serde_json::json;
+    use std::{
+        io::{Read, Write},
+        os::unix::net::UnixStream,
+        thread::sleep,
+        time::Duration,
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn head() {
+        let ((method, path, headers), body) =
+            parse_head(b"GET /programs HTTP/1.1\r\nHost: localhost\r\n\r\nbody")
+                .unwrap()
+                .unwrap();
+        assert_eq!(method, "GET");
+        assert_eq!(path, "/programs");
+        assert_eq!(headers, vec![("Host".to_string(), "localhost".to_string())]);
+        assert_eq!(body, b"body");
+    }
+    #[test]
+    fn base64() {
+        assert_eq!(base64_decode("dXNlcjpzZWNyZXQ=").unwrap(), b"user:secret");
+        assert_eq!(base64_decode("YQ==").unwrap(), b"a");
+        assert!(base64_decode("not base64!").is_none());
+    }
+    #[test]
+    fn token_comparison() {
+        assert!(constant_time_eq("secret", "secret"));
+        assert!(!constant_time_eq("secreT", "secret"));
+        assert!(!constant_time_eq("secret2", "secret"));
+        assert!(!constant_time_eq("", "secret"));
+        assert!(!constant_time_eq("sec", "secret"));
+    }
+    #[test]
+    fn incomplete_head() {
+        assert!(parse_head(b"GET / HTTP/1.1\r\nHost: local")
+            .unwrap()
+            .is_none());
+    }
+    #[test]
+    fn non_loopback() {
+        let config = HttpConfig {
+            listen: "0.0.0.0:0".to_string(),
+            token: "secret".to_string(),
+        };
+        assert!(HttpServer::bind(&config).is_err());
+    }
+    #[test]
+    fn unix_request() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("http.sock");
+        let mut server = HttpServer::bind(&HttpConfig {
+            listen: format!("unix:{}", path.display()),
+            token: "secret".to_string(),
+        })
+        .unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(b"POST /reload HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Length: 2\r\n\r\n{}")
+            .unwrap();
+        sleep(Duration::from_millis(50));
+        let request = server.poll().pop().unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.body, b"{}");
+        assert!(server.is_authorized(&request));
+        request.reply(Response::json(200, json!({"ok": true})));
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n{\"ok\":true}"));
+    }
+}
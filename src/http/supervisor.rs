@@ -0,0 +1,375 @@
+//! supervisord compatible XML-RPC interface, served on `/RPC2`.
+//!
+//! Every program is a supervisord group, and each of its processes is named after the program,
+//! with a `_<index>` suffix when the program has more than one process.
+//! The `wait` arguments are ignored: the calls return as soon as the signals are sent or the
+//! processes spawned.
+
+use super::{
+    xmlrpc::{self, structure, Fault, Value},
+    Response,
+};
+use crate::{
+    config::Config,
+    program::{
+        child::{ProcessState, Status},
+        Program,
+    },
+};
+use std::{
+    process,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::{debug, info};
+
+const UNKNOWN_METHOD: i32 = 1;
+const INCORRECT_PARAMETERS: i32 = 2;
+const SHUTDOWN_STATE: i32 = 6;
+const BAD_NAME: i32 = 10;
+const SPAWN_ERROR: i32 = 50;
+const ALREADY_STARTED: i32 = 60;
+const NOT_RUNNING: i32 = 70;
+const SUCCESS: i32 = 80;
+
+const METHODS: &[&str] = &[
+    "system.listMethods",
+    "supervisor.getAPIVersion",
+    "supervisor.getSupervisorVersion",
+    "supervisor.getIdentification",
+    "supervisor.getState",
+    "supervisor.getPID",
+    "supervisor.getAllProcessInfo",
+    "supervisor.getProcessInfo",
+    "supervisor.startProcess",
+    "supervisor.stopProcess",
+    "supervisor.startProcessGroup",
+    "supervisor.stopProcessGroup",
+    "supervisor.startAllProcesses",
+    "supervisor.stopAllProcesses",
+    "supervisor.shutdown",
+];
+
+/// a process or a whole group, from the `group:name`, `group:*` or `name` syntax
+enum Target {
+    Group(usize),
+    Process(usize, usize),
+}
+
+pub fn handle(config: &mut Config, body: &[u8]) -> Response {
+    let result = std::str::from_utf8(body)
+        .map_err(|e| e.to_string())
+        .and_then(xmlrpc::parse_call)
+        .map_err(|e| fault(INCORRECT_PARAMETERS, "INCORRECT_PARAMETERS", e))
+        .and_then(|(method, params)| call(config, &method, &params));
+    Response {
+        status: 200,
+        content_type: "text/xml",
+        body: xmlrpc::response(result).into_bytes(),
+    }
+}
+
+fn call(config: &mut Config, method: &str, params: &[Value]) -> Result<Value, Fault> {
+    debug!(method, "XML-RPC call");
+    match method {
+        "system.listMethods" => Ok(Value::Array(METHODS.iter().map(|&m| m.into()).collect())),
+        "supervisor.getAPIVersion" => Ok("3.0".into()),
+        "supervisor.getSupervisorVersion" => Ok(env!("CARGO_PKG_VERSION").into()),
+        "supervisor.getIdentification" => Ok(env!("CARGO_PKG_NAME").into()),
+        "supervisor.getState" => Ok(match config.pending_quit {
+            true => structure([
+                ("statecode", Value::Int(-1)),
+                ("statename", "SHUTDOWN".into()),
+            ]),
+            false => structure([
+                ("statecode", Value::Int(1)),
+                ("statename", "RUNNING".into()),
+            ]),
+        }),
+        "supervisor.getPID" => Ok(Value::Int(process::id().into())),
+        "supervisor.getAllProcessInfo" => Ok(Value::Array(
+            config
+                .program
+                .iter()
+                .flat_map(|p| (0..p.processes as usize).map(|i| process_info(p, i)))
+                .collect(),
+        )),
+        "supervisor.getProcessInfo" => {
+            let name = name_param(params)?;
+            match find(config, name)? {
+                Target::Process(p, i) => Ok(process_info(&config.program[p], i)),
+                Target::Group(_) => Err(fault(BAD_NAME, "BAD_NAME", name)),
+            }
+        }
+        "supervisor.startProcess" => {
+            refuse_when_quitting(config)?;
+            match find(config, name_param(params)?)? {
+                Target::Process(p, i) => start(config, p, i).map(|_| true.into()),
+                Target::Group(p) => Ok(start_group(config, p)),
+            }
+        }
+        "supervisor.stopProcess" => match find(config, name_param(params)?)? {
+            Target::Process(p, i) => stop(&mut config.program[p], i).map(|_| true.into()),
+            Target::Group(p) => Ok(stop_group(&mut config.program[p])),
+        },
+        "supervisor.startProcessGroup" => {
+            refuse_when_quitting(config)?;
+            let p = find_group(config, name_param(params)?)?;
+            Ok(start_group(config, p))
+        }
+        "supervisor.stopProcessGroup" => {
+            let p = find_group(config, name_param(params)?)?;
+            Ok(stop_group(&mut config.program[p]))
+        }
+        "supervisor.startAllProcesses" => {
+            refuse_when_quitting(config)?;
+            Ok(concat(
+                (0..config.program.len()).map(|p| start_group(config, p)),
+            ))
+        }
+        "supervisor.stopAllProcesses" => Ok(concat(config.program.iter_mut().map(stop_group))),
+        "supervisor.shutdown" => {
+            info!("Shutdown requested over XML-RPC");
+            config.quit();
+            Ok(true.into())
+        }
+        _ => Err(fault(UNKNOWN_METHOD, "UNKNOWN_METHOD", method)),
+    }
+}
+
+fn fault(code: i32, name: &str, detail: impl AsRef<str>) -> Fault {
+    Fault {
+        code,
+        message: format!("{name}: {}", detail.as_ref()),
+    }
+}
+
+fn name_param(params: &[Value]) -> Result<&str, Fault> {
+    params.first().and_then(Value::as_str).ok_or_else(|| {
+        fault(
+            INCORRECT_PARAMETERS,
+            "INCORRECT_PARAMETERS",
+            "expected a name",
+        )
+    })
+}
+
+fn refuse_when_quitting(config: &Config) -> Result<(), Fault> {
+    match config.pending_quit {
+        true => Err(fault(
+            SHUTDOWN_STATE,
+            "SHUTDOWN_STATE",
+            "taskmaster is quitting",
+        )),
+        false => Ok(()),
+    }
+}
+
+fn find(config: &Config, name: &str) -> Result<Target, Fault> {
+    let bad_name = || fault(BAD_NAME, "BAD_NAME", name);
+    let (group, process) = match name.split_once(':') {
+        Some((group, process)) => (Some(group), process),
+        None => (None, name),
+    };
+    for (p, program) in config.program.iter().enumerate() {
+        if group.is_some_and(|g| g != program.name) {
+            continue;
+        }
+        if process == "*" {
+            return Ok(Target::Group(p));
+        }
+        if let Some(i) =
+            (0..program.processes as usize).find(|&i| program.process_name(i) == process)
+        {
+            return Ok(Target::Process(p, i));
+        }
+        if group.is_some() {
+            return Err(bad_name());
+        }
+    }
+    Err(bad_name())
+}
+
+fn find_group(config: &Config, name: &str) -> Result<usize, Fault> {
+    config
+        .program
+        .iter()
+        .position(|p| p.name == name)
+        .ok_or_else(|| fault(BAD_NAME, "BAD_NAME", name))
+}
+
+/// start one process. the whole program is started if it never was, as childs are kept in order,
+/// once its dependencies are running
+fn start(config: &mut Config, p: usize, index: usize) -> Result<(), Fault> {
+    let program = &config.program[p];
+    let name = program.process_name(index);
+    if program
+        .childs
+        .get(index)
+        .is_some_and(|c| c.status.is_running())
+    {
+        return Err(fault(ALREADY_STARTED, "ALREADY_STARTED", name));
+    }
+    let result = match program.childs.is_empty() {
+        true => config.start_program(&program.name.clone()),
+        false if !config.dependencies_running(p) => Err("its dependencies aren't running".into()),
+        false => config.program[p].start_child(index),
+    };
+    result.map_err(|e| fault(SPAWN_ERROR, "SPAWN_ERROR", format!("{name}: {e}")))
+}
+
+fn stop(program: &mut Program, index: usize) -> Result<(), Fault> {
+    match program.childs.get(index) {
+        Some(child) if child.status.is_running() => {
+            program.stop_child(index);
+            Ok(())
+        }
+        _ => Err(fault(
+            NOT_RUNNING,
+            "NOT_RUNNING",
+            program.process_name(index),
+        )),
+    }
+}
+
+/// start the processes of the program which are not running
+fn start_group(config: &mut Config, p: usize) -> Value {
+    if config.program[p].childs.is_empty() {
+        let name = config.program[p].name.clone();
+        let result = config
+            .start_program(&name)
+            .map_err(|e| fault(SPAWN_ERROR, "SPAWN_ERROR", e.to_string()));
+        let program = &config.program[p];
+        return Value::Array(
+            (0..program.processes as usize)
+                .map(|i| result_info(program, i, result.clone()))
+                .collect(),
+        );
+    }
+    let results = (0..config.program[p].childs.len())
+        .filter(|&i| !config.program[p].childs[i].status.is_running())
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|i| {
+            let result = start(config, p, i);
+            result_info(&config.program[p], i, result)
+        })
+        .collect();
+    Value::Array(results)
+}
+
+fn stop_group(program: &mut Program) -> Value {
+    program.stopped = true;
+    let results = (0..program.childs.len())
+        .filter(|&i| program.childs[i].status.is_running())
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|i| {
+            let result = stop(program, i);
+            result_info(program, i, result)
+        })
+        .collect();
+    Value::Array(results)
+}
+
+fn concat(arrays: impl Iterator<Item = Value>) -> Value {
+    Value::Array(
+        arrays
+            .flat_map(|a| match a {
+                Value::Array(values) => values,
+                value => vec![value],
+            })
+            .collect(),
+    )
+}
+
+/// the per process result of the group and all processes methods
+fn result_info(program: &Program, index: usize, result: Result<(), Fault>) -> Value {
+    let (status, description) = match result {
+        Ok(()) => (SUCCESS, "OK".to_string()),
+        Err(fault) => (fault.code, fault.message),
+    };
+    structure([
+        ("name", program.process_name(index).into()),
+        ("group", program.name.clone().into()),
+        ("status", Value::Int(status.into())),
+        ("description", description.into()),
+    ])
+}
+
+fn process_info(program: &Program, index: usize) -> Value {
+    let child = program.childs.get(index);
+    let state = child.map_or(ProcessState::Stopped, |c| c.state(program));
+    let (start, stop) = match child.map(|c| c.status) {
+        Some(Status::Starting(t) | Status::Running(t) | Status::Terminating(t)) => (unix(t), 0),
+        Some(status) => (0, unix(status.get_instant())),
+        None => (0, 0),
+    };
+    let pid = match child {
+        Some(c) if c.status.is_running() => c.process.id().into(),
+        _ => 0,
+    };
+    let exitstatus = match child.map(|c| c.status) {
+        Some(Status::Finished(_, code)) => code.into(),
+        _ => 0,
+    };
+    let description = match child {
+        None => "Not started".to_string(),
+        Some(c) if state == ProcessState::Running => {
+            let uptime = c.status.get_instant().elapsed().as_secs();
+            format!(
+                "pid {pid}, uptime {}:{:02}:{:02}",
+                uptime / 3600,
+                uptime / 60 % 60,
+                uptime % 60
+            )
+        }
+        Some(c) => c.status.to_string(),
+    };
+    let path = |p: &Option<std::path::PathBuf>| {
+        p.as_ref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_default()
+    };
+    structure([
+        ("name", program.process_name(index).into()),
+        ("group", program.name.clone().into()),
+        ("description", description.into()),
+        ("start", Value::Int(start)),
+        ("stop", Value::Int(stop)),
+        ("now", Value::Int(unix(Instant::now()))),
+        ("state", Value::Int(state as i64)),
+        ("statename", state.name().into()),
+        ("spawnerr", "".into()),
+        ("exitstatus", Value::Int(exitstatus)),
+        ("logfile", path(&program.stdout).into()),
+        ("stdout_logfile", path(&program.stdout).into()),
+        ("stderr_logfile", path(&program.stderr).into()),
+        ("pid", Value::Int(pid)),
+    ])
+}
+
+/// convert a monotonic instant to a unix timestamp
+fn unix(instant: Instant) -> i64 {
+    (SystemTime::now() - instant.elapsed())
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::call;
+    use crate::config::Config;
+
+    #[test]
+    fn start_waits_dependencies() {
+        let mut config = Config::load("tests/depends_on.toml").unwrap();
+        call(&mut config, "supervisor.startProcess", &["web".into()]).unwrap();
+        assert!(config.program[0].waits_dependencies);
+        assert!(config.program[0].childs.is_empty());
+        call(&mut config, "supervisor.startAllProcesses", &[]).unwrap();
+        assert!(config.program[0].childs.is_empty());
+        assert_eq!(config.program[1].childs.len(), 1);
+        config.program[1].kill();
+    }
+}
@@ -0,0 +1,406 @@
+//! Minimal XML-RPC encoding and decoding, enough for the supervisord interface.
+
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    Double(f64),
+    String(String),
+    Array(Vec<Value>),
+    Struct(Vec<(String, Value)>),
+    Nil,
+}
+
+/// error returned to the client as an XML-RPC fault
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fault {
+    pub code: i32,
+    pub message: String,
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn write_xml(&self, out: &mut String) {
+        out.push_str("<value>");
+        match self {
+            Value::Int(i) => {
+                let _ = write!(out, "<int>{i}</int>");
+            }
+            Value::Bool(b) => {
+                let _ = write!(out, "<boolean>{}</boolean>", *b as u8);
+            }
+            Value::Double(d) => {
+                let _ = write!(out, "<double>{d}</double>");
+            }
+            Value::String(s) => {
+                let _ = write!(out, "<string>{}</string>", escape(s));
+            }
+            Value::Array(values) => {
+                out.push_str("<array><data>");
+                values.iter().for_each(|v| v.write_xml(out));
+                out.push_str("</data></array>");
+            }
+            Value::Struct(members) => {
+                out.push_str("<struct>");
+                for (name, value) in members {
+                    let _ = write!(out, "<member><name>{}</name>", escape(name));
+                    value.write_xml(out);
+                    out.push_str("</member>");
+                }
+                out.push_str("</struct>");
+            }
+            Value::Nil => out.push_str("<nil/>"),
+        }
+        out.push_str("</value>");
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Int(i)
+    }
+}
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+/// build a struct value from `(name, value)` pairs
+pub fn structure<const N: usize>(members: [(&str, Value); N]) -> Value {
+    Value::Struct(
+        members
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect(),
+    )
+}
+
+/// serialize the result of a method call
+pub fn response(result: Result<Value, Fault>) -> String {
+    let mut out = String::from("<?xml version=\"1.0\"?>\n<methodResponse>");
+    match result {
+        Ok(value) => {
+            out.push_str("<params><param>");
+            value.write_xml(&mut out);
+            out.push_str("</param></params>");
+        }
+        Err(fault) => {
+            out.push_str("<fault>");
+            structure([
+                ("faultCode", Value::Int(fault.code.into())),
+                ("faultString", fault.message.into()),
+            ])
+            .write_xml(&mut out);
+            out.push_str("</fault>");
+        }
+    }
+    out.push_str("</methodResponse>\n");
+    out
+}
+
+/// parse a `<methodCall>` into the method name and its parameters
+pub fn parse_call(xml: &str) -> Result<(String, Vec<Value>), String> {
+    let mut parser = Parser { xml, pos: 0 };
+    parser.skip_prolog();
+    parser.open("methodCall")?;
+    parser.open("methodName")?;
+    let method = parser.text().trim().to_string();
+    parser.close("methodName")?;
+    let mut params = Vec::new();
+    if !parser.empty("params") && parser.try_open("params") {
+        while parser.try_open("param") {
+            params.push(parser.value()?);
+            parser.close("param")?;
+        }
+        parser.close("params")?;
+    }
+    parser.close("methodCall")?;
+    Ok((method, params))
+}
+
+struct Parser<'a> {
+    xml: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.xml[self.pos..]
+    }
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+    /// skip the `<?xml ...?>` declaration and comments
+    fn skip_prolog(&mut self) {
+        loop {
+            self.skip_whitespace();
+            let end = if self.rest().starts_with("<?") {
+                self.rest().find("?>").map(|i| i + 2)
+            } else if self.rest().starts_with("<!--") {
+                self.rest().find("-->").map(|i| i + 3)
+            } else {
+                return;
+            };
+            self.pos += end.unwrap_or(self.rest().len());
+        }
+    }
+    fn try_consume(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            return true;
+        }
+        false
+    }
+    fn try_open(&mut self, tag: &str) -> bool {
+        self.try_consume(&format!("<{tag}>"))
+    }
+    /// `<tag/>`
+    fn empty(&mut self, tag: &str) -> bool {
+        self.try_consume(&format!("<{tag}/>"))
+    }
+    fn open(&mut self, tag: &str) -> Result<(), String> {
+        self.try_open(tag)
+            .then_some(())
+            .ok_or_else(|| format!("expected <{tag}> at byte {}", self.pos))
+    }
+    fn close(&mut self, tag: &str) -> Result<(), String> {
+        self.try_consume(&format!("</{tag}>"))
+            .then_some(())
+            .ok_or_else(|| format!("expected </{tag}> at byte {}", self.pos))
+    }
+    /// raw text until the next tag, unescaped
+    fn text(&mut self) -> String {
+        let end = self.rest().find('<').unwrap_or(self.rest().len());
+        let text = unescape(&self.rest()[..end]);
+        self.pos += end;
+        text
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        if self.empty("value") {
+            return Ok(Value::String(String::new()));
+        }
+        self.open("value")?;
+        let start = self.pos;
+        let text = self.text();
+        if self.rest().starts_with("</value>") {
+            self.close("value")?;
+            return Ok(Value::String(text));
+        }
+        self.pos = start;
+        self.skip_whitespace();
+        if !self.rest().starts_with('<') {
+            return Err(format!("expected a tag at byte {}", self.pos));
+        }
+        let tag_end = self.rest().find('>').ok_or("unterminated tag")?;
+        let tag = self
+            .rest()
+            .get(1..tag_end)
+            .ok_or_else(|| format!("invalid tag at byte {}", self.pos))?
+            .to_string();
+        let value = match tag.as_str() {
+            "nil/" => {
+                self.pos += tag_end + 1;
+                Value::Nil
+            }
+            "string/" => {
+                self.pos += tag_end + 1;
+                Value::String(String::new())
+            }
+            "int" | "i4" | "i8" => {
+                self.open(&tag)?;
+                let text = self.text();
+                self.close(&tag)?;
+                Value::Int(
+                    text.trim()
+                        .parse()
+                        .map_err(|_| format!("invalid int {text}"))?,
+                )
+            }
+            "boolean" => {
+                self.open(&tag)?;
+                let text = self.text();
+                self.close(&tag)?;
+                Value::Bool(text.trim() == "1")
+            }
+            "double" => {
+                self.open(&tag)?;
+                let text = self.text();
+                self.close(&tag)?;
+                Value::Double(
+                    text.trim()
+                        .parse()
+                        .map_err(|_| format!("invalid double {text}"))?,
+                )
+            }
+            "string" | "dateTime.iso8601" | "base64" => {
+                self.open(&tag)?;
+                let text = self.text();
+                self.close(&tag)?;
+                Value::String(text)
+            }
+            "array" => {
+                self.open("array")?;
+                let mut values = Vec::new();
+                if !self.empty("data") {
+                    self.open("data")?;
+                    while !self.try_consume("</data>") {
+                        values.push(self.value()?);
+                    }
+                }
+                self.close("array")?;
+                Value::Array(values)
+            }
+            "struct" => {
+                self.open("struct")?;
+                let mut members = Vec::new();
+                while self.try_open("member") {
+                    self.open("name")?;
+                    let name = self.text();
+                    self.close("name")?;
+                    members.push((name, self.value()?));
+                    self.close("member")?;
+                }
+                self.close("struct")?;
+                Value::Struct(members)
+            }
+            _ => return Err(format!("unsupported type <{tag}>")),
+        };
+        self.close("value")?;
+        Ok(value)
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let decoded = match &rest[1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_call, response, structure, Fault, Value};
+
+    #[test]
+    fn call_without_params() {
+        let xml = "<?xml version='1.0'?>\n<methodCall>\n<methodName>supervisor.getAllProcessInfo</methodName>\n<params>\n</params>\n</methodCall>\n";
+        assert_eq!(
+            parse_call(xml).unwrap(),
+            ("supervisor.getAllProcessInfo".to_string(), vec![])
+        );
+    }
+    #[test]
+    fn call_with_params() {
+        let xml = "<methodCall><methodName>supervisor.startProcess</methodName><params><param><value><string>web:web_0</string></value></param><param><value><boolean>1</boolean></value></param><param><value>a &amp; b</value></param></params></methodCall>";
+        let (method, params) = parse_call(xml).unwrap();
+        assert_eq!(method, "supervisor.startProcess");
+        assert_eq!(
+            params,
+            vec![
+                Value::String("web:web_0".to_string()),
+                Value::Bool(true),
+                Value::String("a & b".to_string())
+            ]
+        );
+    }
+    #[test]
+    fn nested_params() {
+        let xml = "<methodCall><methodName>m</methodName><params><param><value><array><data><value><int>1</int></value><value><struct><member><name>k</name><value><i4>2</i4></value></member></struct></value></data></array></value></param></params></methodCall>";
+        let (_, params) = parse_call(xml).unwrap();
+        assert_eq!(
+            params,
+            vec![Value::Array(vec![
+                Value::Int(1),
+                Value::Struct(vec![("k".to_string(), Value::Int(2))])
+            ])]
+        );
+    }
+    #[test]
+    fn invalid_call() {
+        assert!(parse_call("<methodCall><params></params></methodCall>").is_err());
+        // text before the type tag
+        for value in [
+            "<value>><int>1</int></value>",
+            "<value>é<int>1</int></value>",
+        ] {
+            let xml = format!("<methodCall><methodName>m</methodName><params><param>{value}</param></params></methodCall>");
+            assert!(parse_call(&xml).is_err(), "{value}");
+        }
+    }
+    #[test]
+    fn success_response() {
+        assert_eq!(
+            response(Ok(structure([("name", "a<b".into()), ("pid", Value::Int(3))]))),
+            "<?xml version=\"1.0\"?>\n<methodResponse><params><param><value><struct><member><name>name</name><value><string>a&lt;b</string></value></member><member><name>pid</name><value><int>3</int></value></member></struct></value></param></params></methodResponse>\n"
+        );
+    }
+    #[test]
+    fn fault_response() {
+        let xml = response(Err(Fault {
+            code: 10,
+            message: "BAD_NAME: foo".to_string(),
+        }));
+        assert!(xml.contains(
+            "<fault><value><struct><member><name>faultCode</name><value><int>10</int></value>"
+        ));
+        assert!(xml.contains("<string>BAD_NAME: foo</string>"));
+    }
+}
@@ -5,8 +5,10 @@ use tracing_subscriber::{
 };
 use tui_logger::tracing_subscriber_layer;
 
+/// log to the file, journald if available, and the tui logger widget when `tui` is set
 pub fn init_logger(
     log_file: &str,
+    tui: bool,
 ) -> Result<reload::Handle<EnvFilter, Registry>, Box<dyn std::error::Error>> {
     let mut file = File::options().append(true).create(true).open(log_file)?;
     if file.metadata()?.created()?.elapsed()? > Duration::from_secs(1) {
@@ -19,8 +21,8 @@ pub fn init_logger(
         reload::Layer::new(EnvFilter::try_from_default_env().unwrap_or(EnvFilter::new("info")));
     registry()
         .with(filter_layer)
-        .with(tracing_subscriber_layer())
-        .with(tracing_journald::layer()?)
+        .with(tui.then(tracing_subscriber_layer))
+        .with(tracing_journald::layer().ok())
         .with(layer().with_writer(file))
         .init();
     Ok(filter_handle)
@@ -36,7 +38,7 @@ mod tests {
         let dir = tempdir().expect("creating tmp dir");
         let mut file = dir.path().to_path_buf();
         file.push("test.log");
-        super::init_logger(file.to_str().unwrap()).unwrap();
+        super::init_logger(file.to_str().unwrap(), true).unwrap();
         log::info!("test");
         assert!(
         read(file).unwrap().ends_with("\u{1b}[0m \u{1b}[32m INFO\u{1b}[0m \u{1b}[2mtaskmaster::logger::tests\u{1b}[0m\u{1b}[2m:\u{1b}[0m test    \n".as_bytes()));
@@ -1,31 +1,180 @@
+mod args;
 mod config;
+mod control;
+mod daemon;
+mod http;
 mod logger;
+mod notify;
 mod program;
+mod state;
 mod tui;
+mod wait;
 
-use config::Config;
-use libc::{c_void, sighandler_t, SIGHUP};
-use program::StartPolicy;
+use args::Args;
+use config::{Config, Signal};
+use control::ControlSocket;
+use daemon::{InstanceLock, Pidfile};
+use http::HttpServer;
+use libc::{SIGCHLD, SIGHUP, SIGINT, SIGQUIT, SIGTERM};
+use notify::Notifier;
+use program::{Program, StartPolicy};
+use state::StateFile;
 use std::{
-    env::args,
+    env,
     error::Error,
-    mem,
-    sync::atomic::{AtomicBool, Ordering},
-    time::Duration,
+    io,
+    path::Path,
+    process::{self, ExitCode},
+    time::{Duration, Instant},
 };
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn, Level};
 use tui::{Command, Tui};
+use wait::Waiter;
 
-static mut RELOAD: AtomicBool = AtomicBool::new(false);
-fn sighup_handler() {
-    info!("Received SIGHUP");
-    unsafe { RELOAD.store(true, Ordering::Relaxed) }
+/// how often the tui is redrawn when nothing happens, for its timers
+const TUI_REFRESH: Duration = Duration::from_millis(250);
+
+/// run a command from the tui or the control socket, and describe what has been done
+fn execute(config: &mut Config, command: Command) -> Result<String, Box<dyn Error>> {
+    match command {
+        Command::Quit => {
+            if config.pending_quit {
+                warn!("Force quitting");
+                config.force_quit = true;
+                return Ok("Force quitting".to_string());
+            }
+            config.quit();
+            Ok("Gracefully shutting down programs".to_string())
+        }
+        Command::LogLevel(level) => {
+            info!(%level, "Changing log level");
+            config.loglevel = level;
+            config.reload_tracing_level()?;
+            Ok(format!("Log level set to {level}"))
+        }
+        Command::Reload(mut path) => {
+            if path.is_empty() {
+                path = config.path.display().to_string();
+            }
+            let new_config = Config::load(&path)
+                .map_err(|e| format!("reloading the configuration file {path}: {e}"))?;
+            config.update(new_config)?;
+            Ok(format!("Configuration reloaded from {path}"))
+        }
+        Command::Reexec => {
+            if config.pending_quit {
+                return Err("Taskmaster is shutting down".into());
+            }
+            info!("Re-executing taskmaster");
+            config.pending_reexec = true;
+            Ok("Re-executing taskmaster".to_string())
+        }
+        Command::Start(name) if name.is_empty() => {
+            info!("Starting all programs");
+            let mut errors = Vec::new();
+            let names: Vec<_> = config.program.iter().map(|p| p.name.clone()).collect();
+            for name in names {
+                if let Err(e) = config.start_program(&name) {
+                    errors.push(format!("{name}: {e}"));
+                }
+            }
+            if !errors.is_empty() {
+                return Err(errors.join("\n").into());
+            }
+            Ok("All programs started".to_string())
+        }
+        Command::Start(name) => {
+            info!(name, "Starting");
+            let mut errors = Vec::new();
+            let names: Vec<_> = config
+                .find_programs(&name)?
+                .iter()
+                .map(|p| p.name.clone())
+                .collect();
+            for name in names {
+                if let Err(e) = config.start_program(&name) {
+                    errors.push(format!("{name}: {e}"));
+                }
+            }
+            if !errors.is_empty() {
+                return Err(errors.join("\n").into());
+            }
+            Ok(format!("{name} started"))
+        }
+        Command::Stop(name) if name.is_empty() => {
+            info!("Stopping all programs");
+            for program in &mut config.program {
+                program.stop();
+            }
+            Ok("Stopping all programs".to_string())
+        }
+        Command::Stop(name) => {
+            info!(name, "Stopping");
+            config
+                .find_programs(&name)?
+                .into_iter()
+                .for_each(Program::stop);
+            Ok(format!("Stopping {name}"))
+        }
+        Command::Restart(name) if name.is_empty() => {
+            info!("Restarting all programs");
+            for program in &mut config.program {
+                program.restart();
+            }
+            Ok("Restarting all programs".to_string())
+        }
+        Command::Restart(name) => {
+            info!(name, "Restarting");
+            config
+                .find_programs(&name)?
+                .into_iter()
+                .for_each(Program::restart);
+            Ok(format!("Restarting {name}"))
+        }
+        Command::Status(name) if name.is_empty() => Ok(config
+            .program
+            .iter()
+            .map(|p| p.report())
+            .collect::<Vec<_>>()
+            .join("\n")),
+        Command::Status(name) => Ok(config
+            .find_programs(&name)?
+            .iter()
+            .map(|p| p.report())
+            .collect::<Vec<_>>()
+            .join("\n")),
+    }
+}
+
+/// `taskmaster check`: print the problems of the configuration file, without running it
+fn check(path: &str) -> ExitCode {
+    // the warnings of Config::load, like the unsupported supervisord options
+    tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_max_level(Level::WARN)
+        .without_time()
+        .init();
+    let problems = config::check::check(Path::new(path));
+    for problem in &problems {
+        eprintln!("{problem}");
+    }
+    match problems.is_empty() {
+        true => {
+            println!("{path}: ok");
+            ExitCode::SUCCESS
+        }
+        false => ExitCode::FAILURE,
+    }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let tracing_filter_handle =
-        logger::init_logger("taskmaster.log").map_err(|e| format!("starting tracing: {e}"))?;
-    let config_path = args().nth(1).unwrap_or("config/default.toml".to_string());
+fn main() -> Result<ExitCode, Box<dyn Error>> {
+    let args = Args::parse(env::args().skip(1))?;
+    if args.check {
+        return Ok(check(&args.config_path));
+    }
+    let tracing_filter_handle = logger::init_logger("taskmaster.log", !args.headless)
+        .map_err(|e| format!("starting tracing: {e}"))?;
+    let config_path = args.config_path;
     let mut config = match Config::load(&config_path) {
         Ok(v) => v,
         Err(e) => {
@@ -35,124 +184,202 @@ fn main() -> Result<(), Box<dyn Error>> {
     };
     config.tracing_filter_handle = Some(tracing_filter_handle);
     config.reload_tracing_level()?;
-    unsafe {
-        libc::signal(SIGHUP, sighup_handler as *mut c_void as sighandler_t);
+    let _lock = InstanceLock::acquire(&config_path, &config.socket)?;
+    let mut control = ControlSocket::bind(&config.socket)
+        .map_err(|e| format!("binding the control socket {:?}: {e}", config.socket))?;
+    let mut http = config
+        .http
+        .as_ref()
+        .map(HttpServer::bind)
+        .transpose()
+        .map_err(|e| format!("starting the HTTP server: {e}"))?;
+    // a re-executed taskmaster is already detached
+    if args.daemon && env::var_os(state::REEXEC_ENV).is_none() {
+        info!("Detaching in the background");
+        daemon::daemonize()?;
     }
-    let mut tui = Tui::new()?;
-    for program in &mut config.program {
-        if let StartPolicy::Auto = program.start_policy {
-            if let Err(e) = program.start() {
-                error!(error = e, name = program.name, "starting program");
+    let _pidfile = config.pidfile.as_ref().map(Pidfile::create).transpose()?;
+    let mut notifier = Notifier::from_env();
+    // SIGHUP reloads the configuration, the others quit gracefully, and forcefully the second time
+    wait::handle_signals(&[SIGHUP, SIGTERM, SIGINT, SIGQUIT])?;
+    let mut tui = if args.headless {
+        info!("Running headless");
+        None
+    } else {
+        Some(Tui::new()?)
+    };
+    let waiter = Waiter::new()?;
+    // every orphan of the pid namespace is reparented to its init process
+    config.init = process::id() == 1;
+    if config.init {
+        info!("Running as init");
+    } else if let Err(e) = program::orphan::set_subreaper() {
+        warn!(error = %e, "Couldn't become a subreaper, orphaned processes won't be tracked");
+    }
+    let mut state = config.statefile.as_ref().map(StateFile::new);
+    let reexecuted = state::restore_reexec(&mut config).unwrap_or_else(|e| {
+        error!(error = e, "restoring the state after re-exec");
+        true
+    });
+    if let Some(state) = state.as_ref().filter(|_| !reexecuted) {
+        state.restore(&mut config);
+    }
+    // the orphans of the previous execution are still children of taskmaster
+    config.adopt_orphans();
+    let mut autostart = Vec::new();
+    for program in config.program.iter_mut().filter(|_| !reexecuted) {
+        // the adopted programs are already started
+        if program.start_policy == StartPolicy::Auto && program.childs.is_empty() {
+            autostart.push(program.name.clone());
+        }
+        if program.start_policy == StartPolicy::OnDemand {
+            if let Err(e) = program.listen() {
+                error!(error = e, name = program.name, "listening");
             }
         }
     }
+    // the others wait for their dependencies, in start_waiting
+    for name in autostart {
+        if let Err(e) = config.start_program(&name) {
+            error!(error = e, name, "starting program");
+        }
+    }
 
-    let mut pending_quit = false;
     loop {
-        if pending_quit && config.program.iter().all(|p| p.all_stopped()) {
-            info!("All programs have stopped. Quitting");
-            break;
-        }
-        if unsafe { RELOAD.load(Ordering::Relaxed) } {
-            match Config::load(&config_path) {
-                Ok(new_config) => config.update(new_config)?,
-                Err(e) => {
-                    error!(error = e, "reloading the configuration file");
-                }
+        for signal in wait::take_signals() {
+            if signal == SIGCHLD {
+                config.adopt_orphans();
+                continue;
+            }
+            let name = Signal::try_from(signal).map_or(signal.to_string(), |s| format!("{s:?}"));
+            info!(signal = name, "Received signal");
+            let command = match signal {
+                SIGHUP => Command::Reload(String::new()),
+                _ => Command::Quit,
+            };
+            if let Err(e) = execute(&mut config, command) {
+                error!(error = e, signal = name, "handling signal");
             }
-            unsafe { RELOAD.store(false, Ordering::Relaxed) };
         }
-        tui.draw(&config.program)?;
         for program in &mut config.program {
             program.tick()?;
         }
-        for name in mem::take(&mut config.program_deletions).into_iter() {
-            if let Some(position) = config.program.iter().position(|p| p.name == name) {
-                if config.program[position].all_stopped() {
-                    info!(name, "Removing program");
-                    config.program.remove(position);
-                } else {
-                    config.program_deletions.push(name);
-                }
+        config.stop_deleted();
+        config.restart_changed();
+        for request in control.poll() {
+            debug!(
+                command = request.line,
+                "Received command on the control socket"
+            );
+            let result = request.command().and_then(|command| {
+                execute(&mut config, command).map_err(|e| {
+                    error!(command = request.line, error = e, "Control command failed");
+                    e.to_string()
+                })
+            });
+            control.reply(request, result);
+        }
+        if let Some(http) = &mut http {
+            for request in http.poll() {
+                debug!(method = request.method, path = request.path, "HTTP request");
+                let response = http.handle(&mut config, &request);
+                request.reply(response);
             }
         }
-        match tui.tick(Duration::from_millis(10))? {
-            Some(Command::Quit) => {
-                if pending_quit {
-                    warn!("Force quitting");
-                    break;
-                }
-                info!("Gracefully shutting down programs");
-                pending_quit = true;
-                for program in &mut config.program {
-                    program.stop();
-                }
+        let command = match &mut tui {
+            Some(tui) => tui.tick(Duration::ZERO)?,
+            None => None,
+        };
+        if let Some(command) = command {
+            let is_status = matches!(command, Command::Status(_));
+            match execute(&mut config, command) {
+                Ok(report) if is_status => report.lines().for_each(|line| info!("{line}")),
+                Ok(_) => (),
+                Err(e) => error!(error = e, "Command failed"),
             }
-            Some(Command::LogLevel(level)) => {
-                info!(%level, "Changing log level");
-                config.loglevel = level;
-                config.reload_tracing_level()?;
+        }
+        config.start_on_demand();
+        config.start_waiting();
+        config.dispatch_events();
+        if config.init && !config.pending_quit && config.all_exited() {
+            info!("All programs have exited");
+            config.quit();
+        }
+        // the programs waiting for their dependents to stop
+        if config.pending_quit {
+            config.stop_next();
+        }
+        if let Some(notifier) = &mut notifier {
+            notifier.tick(&config);
+        }
+        if config.pending_reexec && config.program.iter().all(|p| p.listeners_idle()) {
+            // the terminal is given back before the new execution takes it
+            let terminal = tui.take().is_some();
+            if let Some(notifier) = &notifier {
+                notifier.send("RELOADING=1");
             }
-            Some(Command::Reload(mut path)) => {
-                if path.is_empty() {
-                    path = config_path.clone()
-                }
-                match Config::load(&path) {
-                    Ok(new_config) => config.update(new_config)?,
-                    Err(e) => {
-                        error!(path, error = e, "reloading the configuration file")
-                    }
-                }
+            let error = state::reexec(&config);
+            error!(error, "Couldn't re-execute taskmaster");
+            config.pending_reexec = false;
+            if let Some(notifier) = &notifier {
+                notifier.send("READY=1");
             }
-            Some(Command::Start(name)) => {
-                if name.is_empty() {
-                    info!("Starting all programs");
-                    for program in &mut config.program {
-                        if let Err(e) = program.start() {
-                            error!(error = e, name = program.name, "Starting program");
-                        }
-                    }
-                } else if let Some(p) = config.program.iter_mut().find(|p| p.name == name) {
-                    info!(name, "Starting");
-                    if let Err(e) = p.start() {
-                        error!(error = e, "Starting");
-                    }
-                } else {
-                    error!(name, "Program not found");
-                }
+            if terminal {
+                tui = Some(Tui::new()?);
             }
-            Some(Command::Stop(name)) => {
-                if name.is_empty() {
-                    info!("Stopping all programs");
-                    for program in &mut config.program {
-                        program.stop();
-                    }
-                } else if let Some(p) = config.program.iter_mut().find(|p| p.name == name) {
-                    info!(name, "Stopping");
-                    p.stop()
-                } else {
-                    error!(name, "Program not found");
-                }
+        }
+        if config.force_quit {
+            break;
+        }
+        if config.pending_quit && config.program.iter().all(|p| p.all_stopped()) {
+            info!("All programs have stopped. Quitting");
+            break;
+        }
+        if let Some(tui) = &mut tui {
+            tui.draw(&config.program, &config.group)?;
+        }
+        if let Some(state) = &mut state {
+            if let Err(e) = state.save(&config) {
+                error!(error = e, "saving the state file");
             }
-            Some(Command::Restart(name)) => {
-                if name.is_empty() {
-                    info!("Restarting all programs");
-                    for program in &mut config.program {
-                        program.restart();
-                    }
-                } else if let Some(p) = config.program.iter_mut().find(|p| p.name == name) {
-                    info!(name, "Restarting");
-                    p.restart()
-                } else {
-                    error!(name, "Program not found");
-                }
+        }
+
+        // sleep until a child changes state, a client or the terminal sends something,
+        // or a timer expires
+        let mut fds = control.fds();
+        let write_fds = control.write_fds();
+        let mut deadlines = vec![control.deadline()];
+        if let Some(http) = &http {
+            fds.extend(http.fds());
+            deadlines.push(http.deadline());
+        }
+        for program in &config.program {
+            fds.extend(program.fds());
+            if !config.pending_quit {
+                fds.extend(program.demand_fds());
             }
-            None => (),
+            deadlines.push(program.deadline());
         }
+        deadlines.push(notifier.as_ref().and_then(Notifier::deadline));
+        if let Some(tui) = &tui {
+            fds.push(libc::STDIN_FILENO);
+            let now = Instant::now();
+            deadlines.push(Some(match tui.has_pending_input()? {
+                true => now,
+                false => now + TUI_REFRESH,
+            }));
+        }
+        waiter.wait(&fds, &write_fds, deadlines.into_iter().flatten().min())?;
     }
     // kill in case there is still a program running
     for program in &mut config.program {
         program.kill();
     }
-    Ok(())
+    if let Some(state) = &state {
+        state.remove();
+    }
+    Ok(match config.init {
+        true => ExitCode::from(config.exit_code()),
+        false => ExitCode::SUCCESS,
+    })
 }
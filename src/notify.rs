@@ -0,0 +1,222 @@
+//! The systemd notification protocol, for taskmaster running as a `Type=notify` service.
+//!
+//! Messages are datagrams of `KEY=value` lines sent to the socket given in `NOTIFY_SOCKET`,
+//! which can be in the abstract namespace when it starts with `@`.
+
+use crate::{
+    config::Config,
+    program::{
+        child::{ProcessState, Status},
+        StartPolicy,
+    },
+};
+use std::{
+    env, io,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    process,
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+pub const SOCKET_ENV: &str = "NOTIFY_SOCKET";
+pub const WATCHDOG_USEC_ENV: &str = "WATCHDOG_USEC";
+pub const WATCHDOG_PID_ENV: &str = "WATCHDOG_PID";
+
+#[derive(Debug)]
+pub struct Notifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+    /// how often the watchdog must be pinged, half the timeout of the service manager
+    watchdog: Option<Duration>,
+    last_ping: Instant,
+    ready: bool,
+    stopping: bool,
+    status: String,
+}
+
+impl Notifier {
+    /// from the environment given by the service manager, if any
+    pub fn from_env() -> Option<Self> {
+        let path = env::var(SOCKET_ENV).ok()?;
+        let watchdog = env::var(WATCHDOG_USEC_ENV)
+            .ok()
+            .filter(|_| {
+                // the watchdog can be meant for another process of the service
+                env::var(WATCHDOG_PID_ENV).map_or(true, |pid| pid == process::id().to_string())
+            })
+            .and_then(|usec| usec.parse().ok())
+            .filter(|&usec| usec > 0)
+            .map(Duration::from_micros);
+        match Notifier::new(&path, watchdog) {
+            Ok(notifier) => Some(notifier),
+            Err(e) => {
+                warn!(path, error = %e, "Couldn't open the notification socket");
+                None
+            }
+        }
+    }
+
+    pub fn new(path: &str, watchdog: Option<Duration>) -> io::Result<Self> {
+        let address = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(path)?,
+        };
+        Ok(Notifier {
+            socket: UnixDatagram::unbound()?,
+            address,
+            watchdog: watchdog.map(|timeout| timeout / 2),
+            last_ping: Instant::now(),
+            ready: false,
+            stopping: false,
+            status: String::new(),
+        })
+    }
+
+    pub fn send(&self, message: &str) {
+        debug!(message, "Notifying the service manager");
+        if let Err(e) = self.socket.send_to_addr(message.as_bytes(), &self.address) {
+            warn!(error = %e, "notifying the service manager");
+        }
+    }
+
+    /// send what changed since the last call, and ping the watchdog when it is time
+    pub fn tick(&mut self, config: &Config) {
+        let status = status(config);
+        if status != self.status {
+            self.send(&format!("STATUS={status}"));
+            self.status = status;
+        }
+        if !self.ready && started(config) {
+            self.send("READY=1");
+            self.ready = true;
+        }
+        if config.pending_quit && !self.stopping {
+            self.send("STOPPING=1");
+            self.stopping = true;
+        }
+        if let Some(interval) = self.watchdog {
+            if self.last_ping.elapsed() >= interval {
+                self.send("WATCHDOG=1");
+                self.last_ping = Instant::now();
+            }
+        }
+    }
+
+    /// when the watchdog must be pinged next
+    pub fn deadline(&self) -> Option<Instant> {
+        self.watchdog.map(|interval| self.last_ping + interval)
+    }
+}
+
+/// the programs started automatically are running, or settled after failing. those still
+/// waiting for their dependencies aren't
+fn started(config: &Config) -> bool {
+    let programs = config
+        .program
+        .iter()
+        .filter(|p| p.start_policy == StartPolicy::Auto);
+    programs.clone().all(|p| !p.waits_dependencies)
+        && programs
+            .flat_map(|p| p.childs.iter().map(move |c| c.state(p)))
+            .all(|state| !matches!(state, ProcessState::Starting | ProcessState::Backoff))
+}
+
+/// a summary of the childs, for `systemctl status`
+fn status(config: &Config) -> String {
+    let mut running = 0;
+    let mut failed = 0;
+    for program in &config.program {
+        for child in &program.childs {
+            if matches!(child.status, Status::Running(_)) {
+                running += 1;
+            } else if matches!(
+                child.state(program),
+                ProcessState::Backoff | ProcessState::Fatal
+            ) {
+                failed += 1;
+            }
+        }
+    }
+    format!("{running} running, {failed} failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{started, Notifier};
+    use crate::config::Config;
+    use std::{
+        os::{
+            linux::net::SocketAddrExt,
+            unix::net::{SocketAddr, UnixDatagram},
+        },
+        process, thread,
+        time::Duration,
+    };
+    use tempfile::tempdir;
+
+    fn messages(socket: &UnixDatagram) -> Vec<String> {
+        let mut messages = Vec::new();
+        let mut buf = [0; 256];
+        while let Ok(n) = socket.recv(&mut buf) {
+            messages.push(String::from_utf8_lossy(&buf[..n]).to_string());
+        }
+        messages
+    }
+
+    #[test]
+    fn lifecycle() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let mut notifier = Notifier::new(path.to_str().unwrap(), None).unwrap();
+        let mut config: Config =
+            toml::from_str("[[program]]\nname = \"long\"\ncommand = \"sleep\"\nargs = [\"30\"]")
+                .unwrap();
+        config.program[0].start().unwrap();
+        notifier.tick(&config);
+        assert_eq!(messages(&socket), ["STATUS=0 running, 0 failed"]);
+        config.program[0].tick().unwrap();
+        notifier.tick(&config);
+        assert_eq!(messages(&socket), ["STATUS=1 running, 0 failed", "READY=1"]);
+        notifier.tick(&config);
+        assert!(messages(&socket).is_empty());
+        config.quit();
+        notifier.tick(&config);
+        assert_eq!(
+            messages(&socket),
+            ["STATUS=0 running, 0 failed", "STOPPING=1"]
+        );
+        config.program[0].kill();
+    }
+    #[test]
+    fn waiting_dependencies() {
+        let mut config: Config =
+            toml::from_str("[[program]]\nname = \"web\"\ncommand = \"true\"").unwrap();
+        assert!(started(&config));
+        // no childs yet
+        config.program[0].waits_dependencies = true;
+        assert!(!started(&config));
+    }
+    #[test]
+    fn watchdog() {
+        let name = format!("taskmaster-notify-{}", process::id());
+        let address = SocketAddr::from_abstract_name(&name).unwrap();
+        let socket = UnixDatagram::bind_addr(&address).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let timeout = Duration::from_millis(40);
+        let mut notifier = Notifier::new(&format!("@{name}"), Some(timeout)).unwrap();
+        let config: Config = toml::from_str("program = []").unwrap();
+        let first = notifier.deadline().unwrap();
+        notifier.tick(&config);
+        assert!(!messages(&socket).contains(&"WATCHDOG=1".to_string()));
+        // pinged twice per timeout
+        thread::sleep(timeout / 2);
+        notifier.tick(&config);
+        assert!(messages(&socket).contains(&"WATCHDOG=1".to_string()));
+        assert!(notifier.deadline().unwrap() > first);
+    }
+}
@@ -1,15 +1,23 @@
-use crate::config::Signal;
+use crate::{config::Signal, state};
 
-use super::{Program, RestartPolicy};
+use super::{event::Listener, readiness::NotifySocket, Program, Readiness, RestartPolicy};
 use std::{
     error::Error,
-    fmt,
-    os::unix::process::ExitStatusExt,
-    process,
+    fmt, io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::process::ExitStatusExt,
+    },
+    process::{self, ChildStdin, ChildStdout, ExitStatus},
     time::{Duration, Instant},
 };
 use tracing::{debug, error, instrument, trace, warn};
 
+/// delay before restarting a finished child
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+/// reported for the adopted processes which aren't children of taskmaster
+pub const UNKNOWN_EXIT_CODE: i32 = -1;
+
 #[derive(Debug, Clone, Copy)]
 pub enum Status {
     /// The process is not running
@@ -56,6 +64,31 @@ impl Status {
     }
 }
 
+/// The process states of supervisord, for the tools speaking its protocols
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Stopped = 0,
+    Starting = 10,
+    Running = 20,
+    Backoff = 30,
+    Stopping = 40,
+    Exited = 100,
+    Fatal = 200,
+}
+impl ProcessState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProcessState::Stopped => "STOPPED",
+            ProcessState::Starting => "STARTING",
+            ProcessState::Running => "RUNNING",
+            ProcessState::Backoff => "BACKOFF",
+            ProcessState::Stopping => "STOPPING",
+            ProcessState::Exited => "EXITED",
+            ProcessState::Fatal => "FATAL",
+        }
+    }
+}
+
 impl fmt::Display for Status {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -77,30 +110,301 @@ impl fmt::Display for Status {
     }
 }
 
+/// A process spawned by this taskmaster, or adopted from a previous one
+#[derive(Debug)]
+pub enum Process {
+    Spawned(process::Child),
+    /// with a pidfd, readable once the process exited.
+    /// an event listener keeps its pipes across a re-exec
+    Adopted {
+        pid: u32,
+        pidfd: OwnedFd,
+        stdin: Option<ChildStdin>,
+        stdout: Option<ChildStdout>,
+    },
+    /// a process which exited before a re-exec, only kept for its pid
+    Exited(u32),
+}
+
+impl Process {
+    /// open a pidfd on a running process
+    pub fn adopt(pid: u32) -> io::Result<Self> {
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Process::Adopted {
+            pid,
+            pidfd: unsafe { OwnedFd::from_raw_fd(fd as RawFd) },
+            stdin: None,
+            stdout: None,
+        })
+    }
+
+    /// give back its pipes to an adopted event listener
+    pub fn attach(&mut self, pipes: (ChildStdin, ChildStdout)) {
+        if let Process::Adopted { stdin, stdout, .. } = self {
+            *stdin = Some(pipes.0);
+            *stdout = Some(pipes.1);
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        match self {
+            Process::Spawned(child) => child.id(),
+            Process::Adopted { pid, .. } | Process::Exited(pid) => *pid,
+        }
+    }
+
+    /// `Some(None)` when an adopted process exited, but isn't a child of taskmaster:
+    /// its exit status is lost
+    pub fn try_wait(&mut self) -> io::Result<Option<Option<ExitStatus>>> {
+        let (pid, pidfd) = match self {
+            Process::Spawned(child) => return child.try_wait().map(|s| s.map(Some)),
+            Process::Adopted { pid, pidfd, .. } => (*pid as libc::pid_t, pidfd),
+            Process::Exited(_) => return Ok(Some(None)),
+        };
+        let mut status = 0;
+        match unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) } {
+            0 => return Ok(None),
+            -1 => (),
+            _ => return Ok(Some(Some(ExitStatus::from_raw(status)))),
+        }
+        let mut pollfd = libc::pollfd {
+            fd: pidfd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut pollfd, 1, 0) } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Ok(None),
+            _ => Ok(Some(None)),
+        }
+    }
+
+    pub fn stdin(&mut self) -> Option<&mut ChildStdin> {
+        match self {
+            Process::Spawned(child) => child.stdin.as_mut(),
+            Process::Adopted { stdin, .. } => stdin.as_mut(),
+            Process::Exited(_) => None,
+        }
+    }
+
+    pub fn stdout(&mut self) -> Option<&mut ChildStdout> {
+        match self {
+            Process::Spawned(child) => child.stdout.as_mut(),
+            Process::Adopted { stdout, .. } => stdout.as_mut(),
+            Process::Exited(_) => None,
+        }
+    }
+
+    /// the file descriptors of the piped stdin and stdout
+    pub fn pipes(&self) -> (Option<RawFd>, Option<RawFd>) {
+        let (stdin, stdout) = match self {
+            Process::Spawned(child) => (child.stdin.as_ref(), child.stdout.as_ref()),
+            Process::Adopted { stdin, stdout, .. } => (stdin.as_ref(), stdout.as_ref()),
+            Process::Exited(_) => (None, None),
+        };
+        (
+            stdin.map(AsRawFd::as_raw_fd),
+            stdout.map(AsRawFd::as_raw_fd),
+        )
+    }
+
+    /// a file descriptor readable when the adopted process exits, the spawned ones send SIGCHLD
+    pub fn pidfd(&self) -> Option<RawFd> {
+        match self {
+            Process::Adopted { pidfd, .. } => Some(pidfd.as_raw_fd()),
+            Process::Spawned(_) | Process::Exited(_) => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Child {
-    pub process: process::Child,
+    pub process: Process,
     pub status: Status,
     pub restarts: usize,
+    /// the process number of this child in the program
+    pub index: usize,
+    /// set when the program is an event listener
+    pub listener: Option<Listener>,
+    /// a stop has been requested, the child isn't restarted once it exited
+    pub stopped: bool,
+    /// where the child sends READY=1 and its watchdog pings
+    pub notify: Option<NotifySocket>,
+    /// the last WATCHDOG=1, or the start
+    pub last_ping: Instant,
+    /// killed by the watchdog, restarted whatever the restart policy
+    pub expired: bool,
+    /// the process exited and was waited for, its pid can be reused
+    pub reaped: bool,
+    /// in clock ticks after boot, read once to recognize the process after a restart
+    pub start_time: Option<u64>,
 }
 
 impl Child {
-    pub fn new(child: process::Child) -> Self {
+    pub fn new(child: process::Child, index: usize) -> Self {
         Child {
-            process: child,
+            start_time: state::start_time(child.id()),
+            process: Process::Spawned(child),
             status: Status::Starting(Instant::now()),
             restarts: 0,
+            index,
+            listener: None,
+            stopped: false,
+            notify: None,
+            last_ping: Instant::now(),
+            expired: false,
+            reaped: false,
+        }
+    }
+
+    /// a child left running by a previous taskmaster
+    pub fn adopted(process: Process, index: usize, status: Status, restarts: usize) -> Self {
+        Child {
+            start_time: None,
+            process,
+            status,
+            restarts,
+            index,
+            listener: None,
+            stopped: false,
+            notify: None,
+            last_ping: Instant::now(),
+            expired: false,
+            reaped: false,
+        }
+    }
+
+    /// if the restart policy of the program wants this finished child to be restarted,
+    /// ignoring the delay between restarts
+    pub fn wants_restart(&self, program: &Program) -> bool {
+        let restarts_left =
+            (self.restarts as isize) < program.max_restarts || program.max_restarts == -1;
+        let exited = matches!(self.status, Status::Finished(..) | Status::Terminated(..));
+        !self.stopped
+            && restarts_left
+            && (self.expired && exited
+                || match (self.status, &program.restart_policy) {
+                    (Status::Finished(_, code), RestartPolicy::UnexpectedExit) => {
+                        !program.valid_exit_codes.contains(&code)
+                    }
+                    (Status::Terminated(_, signal), RestartPolicy::UnexpectedExit) => {
+                        program.stop_signal as i32 != signal
+                    }
+                    (Status::Finished(..) | Status::Terminated(..), RestartPolicy::Always) => true,
+                    _ => false,
+                })
+    }
+
+    /// the child tells when it is ready, instead of running for min_runtime.
+    /// the adopted processes lost their notify socket
+    fn waits_ready(&self, program: &Program) -> bool {
+        program.ready == Readiness::Notify && self.notify.is_some()
+    }
+
+    /// when the watchdog expires if the child doesn't ping it
+    fn watchdog_deadline(&self, program: &Program) -> Option<Instant> {
+        let started = matches!(self.status, Status::Starting(_) | Status::Running(_));
+        match self.notify.is_some() && started && !self.expired {
+            true => program.watchdog().map(|timeout| self.last_ping + timeout),
+            false => None,
         }
     }
 
-    fn try_wait(&mut self, program: &Program) -> Result<(), Box<dyn Error>> {
+    /// handle what the child sent on its notify socket
+    fn read_notifications(&mut self, program: &Program) {
+        let Some(notify) = &self.notify else {
+            return;
+        };
+        for line in notify.read(self.process.id()) {
+            match (line.as_str(), self.status) {
+                ("READY=1", Status::Starting(since)) if self.waits_ready(program) => {
+                    debug!(
+                        name = program.name,
+                        pid = self.process.id(),
+                        "child is ready"
+                    );
+                    self.status = Status::Running(since);
+                }
+                ("WATCHDOG=1", _) => self.last_ping = Instant::now(),
+                _ => trace!(name = program.name, line, "notification"),
+            }
+        }
+    }
+
+    /// map the status to the supervisord process state
+    pub fn state(&self, program: &Program) -> ProcessState {
+        let expected = match self.status {
+            Status::Stopped(_) => return ProcessState::Stopped,
+            Status::Starting(_) => return ProcessState::Starting,
+            Status::Running(_) => return ProcessState::Running,
+            Status::Terminating(_) => return ProcessState::Stopping,
+            Status::Finished(..) | Status::Terminated(..) if self.stopped => {
+                return ProcessState::Stopped
+            }
+            Status::Terminated(_, signal) if signal == program.stop_signal as i32 => {
+                return ProcessState::Stopped
+            }
+            Status::Finished(_, code) => program.valid_exit_codes.contains(&code),
+            Status::Terminated(..) => false,
+        };
+        if self.wants_restart(program) {
+            ProcessState::Backoff
+        } else if !expected && program.restart_policy != RestartPolicy::Never {
+            ProcessState::Fatal
+        } else {
+            ProcessState::Exited
+        }
+    }
+
+    /// when the status will change without the process doing anything: the end of min_runtime,
+    /// of the graceful shutdown, or of the delay before a restart
+    pub fn deadline(&self, program: &Program) -> Option<Instant> {
+        let deadline = match self.status {
+            Status::Starting(_) if self.waits_ready(program) => None,
+            Status::Starting(since) => Some(since + program.min_runtime),
+            Status::Terminating(since) => Some(since + program.graceful_timeout),
+            Status::Finished(since, _) | Status::Terminated(since, _)
+                if self.wants_restart(program) =>
+            {
+                Some(since + RESTART_DELAY)
+            }
+            _ => None,
+        };
+        deadline
+            .into_iter()
+            .chain(self.watchdog_deadline(program))
+            .min()
+    }
+
+    fn try_wait(&mut self, program: &mut Program) -> Result<(), Box<dyn Error>> {
+        let from = self.state(program);
         let status = match self.process.try_wait() {
-            Ok(Some(status)) if self.status.is_running() => status,
+            Ok(Some(status)) => {
+                self.reaped = true;
+                status
+            }
             Err(e) => {
                 warn!("couldn't get the status of the child process, weird: {e:?}");
                 return Err(e.into());
             }
-            _ => return Ok(()),
+            Ok(None) => return Ok(()),
+        };
+        // killed after the graceful timeout, or already known to have exited
+        if !self.status.is_running() {
+            return Ok(());
+        }
+        let Some(status) = status else {
+            self.status = Status::Finished(Instant::now(), UNKNOWN_EXIT_CODE);
+            debug!(
+                pid = self.process.id(),
+                name = program.name,
+                "adopted process exited, with an unknown exit code"
+            );
+            program.record_transition(self, from);
+            return Ok(());
         };
         if let Some(sig) = status.signal() {
             self.status = Status::Terminated(Instant::now(), sig);
@@ -122,101 +426,124 @@ impl Child {
                 "child process finished"
             );
         };
+        program.record_transition(self, from);
         Ok(())
     }
 
+    #[instrument(skip_all, fields(index = self.index))]
     pub fn tick(&mut self, program: &mut Program) -> Result<(), Box<dyn Error>> {
         self.try_wait(program)?;
-        // the third match condition is for restarting; timeout of 1 second between restart, and limited to max_restarts
-        match (
-            self.status,
-            &program.restart_policy,
-            self.status.get_instant().elapsed() > Duration::from_secs(1)
-                && ((self.restarts as isize) < program.max_restarts || program.max_restarts == -1),
-        ) {
-            (Status::Terminating(since), _, _) if since.elapsed() > program.graceful_timeout => {
+        let from = self.state(program);
+        self.read_notifications(program);
+        // delay between restarts, and limited to max_restarts
+        let restart =
+            self.status.get_instant().elapsed() > RESTART_DELAY && self.wants_restart(program);
+        match self.status {
+            Status::Terminating(since) if since.elapsed() > program.graceful_timeout => {
                 warn!(
                     pid = self.process.id(),
                     name = program.name,
                     "graceful shutdown timeout, killing the child"
                 );
-                self.kill();
+                self.kill(program.kill_as_group());
             }
-            (Status::Finished(_, code), RestartPolicy::UnexpectedExit, true)
-                if !program.valid_exit_codes.contains(&code) =>
-            {
+            Status::Finished(_, code) if restart => {
                 debug!(
                     name = program.name,
                     exit_code = code,
                     "restarting a finished child"
                 );
-                self.restarts += 1;
-                let child = program.create_child()?;
-                self.process = child.process;
-                self.status = child.status;
+                self.respawn(program)?;
             }
-            (Status::Finished(_, code), RestartPolicy::Always, true) => {
-                debug!(
-                    name = program.name,
-                    exit_code = code,
-                    "restarting a finished child"
-                );
-                self.restarts += 1;
-                let child = program.create_child()?;
-                self.process = child.process;
-                self.status = child.status;
-            }
-            (Status::Terminated(_, code), RestartPolicy::UnexpectedExit, true)
-                if program.stop_signal as i32 != code =>
-            {
+            Status::Terminated(_, code) if restart => {
                 debug!(
                     name = program.name,
                     signal = code,
                     "restarting a terminated child"
                 );
-                self.restarts += 1;
-                let child = program.create_child()?;
-                self.process = child.process;
-                self.status = child.status;
+                self.respawn(program)?;
             }
-            (Status::Terminated(_, code), RestartPolicy::Always, true) => {
-                debug!(
+            _ if self
+                .watchdog_deadline(program)
+                .is_some_and(|deadline| deadline <= Instant::now()) =>
+            {
+                warn!(
+                    pid = self.process.id(),
                     name = program.name,
-                    signal = code,
-                    "restarting a terminated child"
+                    "watchdog timeout, killing the child"
                 );
-                self.restarts += 1;
-                let child = program.create_child()?;
-                self.process = child.process;
-                self.status = child.status;
+                self.expired = true;
+                if let Err(e) = self.signal(libc::SIGKILL, program.kill_as_group()) {
+                    error!(pid = self.process.id(), error = ?e, "couldn't kill the child");
+                }
             }
-            (Status::Starting(since), _, _) if since.elapsed() > program.min_runtime => {
+            Status::Starting(since)
+                if !self.waits_ready(program) && since.elapsed() > program.min_runtime =>
+            {
                 self.status = Status::Running(since);
                 trace!(name = program.name, "child is now considered as running");
             }
             _ => (),
         };
+        program.record_transition(self, from);
+        Ok(())
+    }
+
+    /// replace the finished process by a new one
+    fn respawn(&mut self, program: &mut Program) -> Result<(), Box<dyn Error>> {
+        self.restarts += 1;
+        let child = match program.create_child(self.index, self.restarts) {
+            Ok(child) => child,
+            Err(e) => {
+                // wait for the restart delay again before the next try
+                self.status = match self.status {
+                    Status::Finished(_, code) => Status::Finished(Instant::now(), code),
+                    Status::Terminated(_, signal) => Status::Terminated(Instant::now(), signal),
+                    status => status,
+                };
+                return Err(e);
+            }
+        };
+        self.process = child.process;
+        self.status = child.status;
+        self.listener = child.listener;
+        self.notify = child.notify;
+        self.last_ping = child.last_ping;
+        self.expired = false;
+        self.reaped = false;
+        self.start_time = child.start_time;
         Ok(())
     }
 
-    /// Kill the child. for graceful shutdown, check stop().
-    #[instrument(skip_all)]
-    pub fn kill(&mut self) {
+    /// Kill the child, and its process group with `group`. for graceful shutdown, check stop().
+    #[instrument(skip_all, fields(index = self.index))]
+    pub fn kill(&mut self, group: bool) {
         if self.status.is_running() {
-            if let Err(e) = self.process.kill() {
+            if let Err(e) = self.signal(libc::SIGKILL, group) {
                 error!(pid = self.process.id(), error = ?e, "couldn't kill the child");
             }
             self.status = Status::Stopped(Instant::now());
+            self.stopped = true;
         }
     }
-    /// gracefully stop the child
-    #[instrument(skip_all)]
-    pub fn stop(&mut self, signal: i32) {
-        if self.status.is_running() {
-            if unsafe { libc::kill(self.process.id() as i32, signal) } != 0 {
-                error!(pid = self.process.id(), "couldn't send signal to the child");
+    /// gracefully stop the child, and its process group with `group`. the signal is only sent once
+    #[instrument(skip_all, fields(index = self.index))]
+    pub fn stop(&mut self, signal: i32, group: bool) {
+        self.stopped = true;
+        if self.status.is_running() && !matches!(self.status, Status::Terminating(_)) {
+            if let Err(e) = self.signal(signal, group) {
+                error!(pid = self.process.id(), error = ?e, "couldn't send signal to the child");
             }
             self.status = Status::Terminating(Instant::now());
         }
     }
+    /// the child is the leader of its process group, its pid is the group id
+    fn signal(&self, signal: i32, group: bool) -> io::Result<()> {
+        let pid = self.process.id() as i32;
+        let target = if group { -pid } else { pid };
+        match unsafe { libc::kill(target, signal) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
 }
@@ -0,0 +1,238 @@
+use super::{
+    child::{Child, ProcessState, Status},
+    Program,
+};
+use std::{
+    io::{ErrorKind, Read},
+    process::ChildStdout,
+};
+use tracing::{debug, trace, warn};
+
+/// A state change of a child, named like the supervisord PROCESS_STATE events
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub program: String,
+    pub process: String,
+    pub from: ProcessState,
+    pub to: ProcessState,
+    pub status: Status,
+    pub pid: u32,
+    pub restarts: usize,
+    /// if the exit code is one of the valid exit codes, or the child was killed by its stop signal
+    pub expected: bool,
+}
+
+impl Event {
+    /// the event for the child going from the `from` state to its current one, if it changed
+    pub fn transition(program: &Program, child: &Child, from: ProcessState) -> Option<Event> {
+        let to = child.state(program);
+        (from != to).then(|| Event {
+            program: program.name.clone(),
+            process: program.process_name(child.index),
+            from,
+            to,
+            pid: child.process.id(),
+            restarts: child.restarts,
+            status: child.status,
+            expected: match child.status {
+                Status::Finished(_, code) => program.valid_exit_codes.contains(&code),
+                Status::Terminated(_, signal) => {
+                    from == ProcessState::Stopping || signal == program.stop_signal as i32
+                }
+                _ => true,
+            },
+        })
+    }
+
+    pub fn name(&self) -> String {
+        format!("PROCESS_STATE_{}", self.to.name())
+    }
+
+    /// if `event_type` is this event name, or one of its parent types
+    pub fn matches(&self, event_type: &str) -> bool {
+        let name = self.name();
+        event_type == "EVENT"
+            || name == event_type
+            || name
+                .strip_prefix(event_type)
+                .is_some_and(|rest| rest.starts_with('_'))
+    }
+
+    /// if the child just exited, by itself or from a signal
+    pub fn is_exit(&self) -> bool {
+        matches!(self.status, Status::Finished(..) | Status::Terminated(..))
+            && matches!(
+                self.from,
+                ProcessState::Starting | ProcessState::Running | ProcessState::Stopping
+            )
+    }
+
+    /// the body of the event, in the supervisord format
+    pub fn payload(&self) -> String {
+        let mut payload = format!(
+            "processname:{} groupname:{} from_state:{}",
+            self.process,
+            self.program,
+            self.from.name()
+        );
+        match self.to {
+            ProcessState::Starting | ProcessState::Backoff => {
+                payload += &format!(" tries:{}", self.restarts)
+            }
+            ProcessState::Exited => {
+                payload += &format!(" expected:{} pid:{}", self.expected as u8, self.pid)
+            }
+            ProcessState::Running | ProcessState::Stopping | ProcessState::Stopped => {
+                payload += &format!(" pid:{}", self.pid)
+            }
+            ProcessState::Fatal => (),
+        }
+        payload
+    }
+}
+
+#[derive(Debug, Default)]
+pub enum ListenerState {
+    /// waiting for the listener to send READY
+    #[default]
+    Acknowledged,
+    Ready,
+    /// an event has been sent, waiting for its RESULT
+    Busy(usize, Event),
+}
+
+/// The protocol side of a child which is an event listener
+#[derive(Debug, Default)]
+pub struct Listener {
+    pub state: ListenerState,
+    /// the listener closed its stdout
+    pub closed: bool,
+    buffer: Vec<u8>,
+}
+
+impl Listener {
+    /// read what the listener wrote on its stdout.
+    /// returns the event being processed if it has to be sent again: on FAIL, or if the
+    /// listener closed its stdout
+    pub fn read(&mut self, stdout: &mut ChildStdout) -> Option<(usize, Event)> {
+        let mut buf = [0; 1024];
+        loop {
+            match stdout.read(&mut buf) {
+                Ok(0) => {
+                    self.closed = true;
+                    return match std::mem::take(&mut self.state) {
+                        ListenerState::Busy(serial, event) => Some((serial, event)),
+                        _ => None,
+                    };
+                }
+                Ok(n) => self.buffer.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!(error = %e, "reading the event listener stdout");
+                    break;
+                }
+            }
+        }
+        self.parse()
+    }
+
+    fn parse(&mut self) -> Option<(usize, Event)> {
+        loop {
+            let newline = self.buffer.iter().position(|&b| b == b'\n')?;
+            let line = String::from_utf8_lossy(&self.buffer[..newline]).to_string();
+            match &self.state {
+                ListenerState::Acknowledged if line == "READY" => {
+                    self.buffer.drain(..=newline);
+                    trace!("event listener is ready");
+                    self.state = ListenerState::Ready;
+                }
+                ListenerState::Busy(..) if line.starts_with("RESULT ") => {
+                    let Ok(len) = line["RESULT ".len()..].trim().parse::<usize>() else {
+                        warn!(line, "invalid event listener result");
+                        self.buffer.drain(..=newline);
+                        continue;
+                    };
+                    if self.buffer.len() < newline + 1 + len {
+                        return None;
+                    }
+                    let result =
+                        String::from_utf8_lossy(&self.buffer[newline + 1..][..len]).to_string();
+                    self.buffer.drain(..newline + 1 + len);
+                    let ListenerState::Busy(serial, event) = std::mem::take(&mut self.state) else {
+                        unreachable!()
+                    };
+                    if result != "OK" {
+                        debug!(serial, result, "event rejected by the listener");
+                        return Some((serial, event));
+                    }
+                    trace!(serial, "event acknowledged");
+                }
+                _ => {
+                    debug!(line, "unexpected output from the event listener");
+                    self.buffer.drain(..=newline);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, Listener, ListenerState};
+    use crate::program::child::{ProcessState, Status};
+    use std::time::Instant;
+
+    fn exited() -> Event {
+        Event {
+            program: "web".to_string(),
+            process: "web_1".to_string(),
+            from: ProcessState::Running,
+            to: ProcessState::Exited,
+            status: Status::Finished(Instant::now(), 2),
+            pid: 42,
+            restarts: 1,
+            expected: true,
+        }
+    }
+
+    #[test]
+    fn name() {
+        assert_eq!(exited().name(), "PROCESS_STATE_EXITED");
+    }
+    #[test]
+    fn matches() {
+        let event = exited();
+        assert!(event.matches("PROCESS_STATE_EXITED"));
+        assert!(event.matches("PROCESS_STATE"));
+        assert!(event.matches("EVENT"));
+        assert!(!event.matches("PROCESS_STATE_FATAL"));
+        assert!(!event.matches("PROCESS_STATE_EXIT"));
+    }
+    #[test]
+    fn payload() {
+        assert_eq!(
+            exited().payload(),
+            "processname:web_1 groupname:web from_state:RUNNING expected:1 pid:42"
+        );
+    }
+    #[test]
+    fn protocol() {
+        let mut listener = Listener::default();
+        listener.buffer.extend_from_slice(b"READY\n");
+        assert!(listener.parse().is_none());
+        assert!(matches!(listener.state, ListenerState::Ready));
+
+        listener.state = ListenerState::Busy(1, exited());
+        listener.buffer.extend_from_slice(b"RESULT 2\nO");
+        assert!(listener.parse().is_none());
+        assert!(matches!(listener.state, ListenerState::Busy(..)));
+        listener.buffer.extend_from_slice(b"KREADY\n");
+        assert!(listener.parse().is_none());
+        assert!(matches!(listener.state, ListenerState::Ready));
+
+        listener.state = ListenerState::Busy(2, exited());
+        listener.buffer.extend_from_slice(b"RESULT 4\nFAIL");
+        assert_eq!(listener.parse().map(|(serial, _)| serial), Some(2));
+        assert!(matches!(listener.state, ListenerState::Acknowledged));
+    }
+}
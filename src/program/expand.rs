@@ -0,0 +1,147 @@
+//! Variables in the configuration of a program, expanded for each child:
+//! `${VAR}` from the environment of taskmaster, `%(program_name)s`, `%(process_num)d`,
+//! `%(here)s` the directory of the configuration file, and `~` at the start of a value.
+//!
+//! The `%(...)` formats are the ones of supervisord, like `%(process_num)02d`. `$${` and `%%(`
+//! are kept as `${` and `%(`.
+
+use std::{env, path::PathBuf};
+
+/// the configuration of a child, with its variables expanded
+#[derive(Debug)]
+pub struct Expanded {
+    pub cmd: PathBuf,
+    pub args: Vec<String>,
+    pub env: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    pub stdout: Option<PathBuf>,
+    pub stderr: Option<PathBuf>,
+}
+
+pub struct Variables<'a> {
+    pub program_name: &'a str,
+    pub process_num: usize,
+    pub here: &'a str,
+    pub home: Option<&'a str>,
+}
+
+pub fn expand(value: &str, variables: &Variables) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = value;
+    if let Some(after) = rest.strip_prefix('~') {
+        if after.is_empty() || after.starts_with('/') {
+            expanded.push_str(variables.home.ok_or("no home directory to expand `~`")?);
+            rest = after;
+        }
+    }
+    while let Some(start) = rest.find(['$', '%']) {
+        expanded.push_str(&rest[..start]);
+        let (sigil, after) = rest[start..].split_at(1);
+        let opening = if sigil == "$" { '{' } else { '(' };
+        // doubled to be kept as is
+        if after.starts_with(sigil) && after[1..].starts_with(opening) {
+            expanded.push(sigil.chars().next().unwrap_or_default());
+            expanded.push(opening);
+            rest = &after[2..];
+            continue;
+        }
+        if !after.starts_with(opening) {
+            expanded.push_str(sigil);
+            rest = after;
+            continue;
+        }
+        let closing = if sigil == "$" { '}' } else { ')' };
+        let end = after
+            .find(closing)
+            .ok_or(format!("unterminated variable in `{value}`"))?;
+        let name = &after[1..end];
+        rest = &after[end + 1..];
+        if sigil == "$" {
+            let variable =
+                env::var(name).map_err(|e| format!("expanding `${{{name}}}` in `{value}`: {e}"))?;
+            expanded.push_str(&variable);
+            continue;
+        }
+        // the format, like `s` or `02d`
+        let format_end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .filter(|&i| matches!(rest.as_bytes()[i], b's' | b'd'))
+            .ok_or(format!("invalid format of `%({name})` in `{value}`"))?;
+        let (width, kind) = (&rest[..format_end], rest.as_bytes()[format_end]);
+        rest = &rest[format_end + 1..];
+        let variable = match name {
+            "program_name" => variables.program_name.to_string(),
+            "process_num" => variables.process_num.to_string(),
+            "here" => variables.here.to_string(),
+            _ => return Err(format!("unknown variable `%({name})` in `{value}`")),
+        };
+        if kind == b'd' && variable.parse::<usize>().is_err() {
+            return Err(format!("`%({name})` isn't a number in `{value}`"));
+        }
+        let padding = width.parse::<usize>().unwrap_or(0);
+        let fill = if width.starts_with('0') { '0' } else { ' ' };
+        for _ in variable.len()..padding {
+            expanded.push(fill);
+        }
+        expanded.push_str(&variable);
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand, Variables};
+
+    const VARIABLES: Variables = Variables {
+        program_name: "web",
+        process_num: 3,
+        here: "/etc/taskmaster",
+        home: Some("/home/web"),
+    };
+
+    #[test]
+    fn variables() {
+        std::env::set_var("TASKMASTER_TEST_VARIABLE", "value");
+        assert_eq!(
+            expand(
+                "%(here)s/%(program_name)s_%(process_num)02d.log",
+                &VARIABLES
+            )
+            .unwrap(),
+            "/etc/taskmaster/web_03.log"
+        );
+        assert_eq!(
+            expand(
+                "--port=80%(process_num)d ${TASKMASTER_TEST_VARIABLE}",
+                &VARIABLES
+            )
+            .unwrap(),
+            "--port=803 value"
+        );
+        assert_eq!(expand("~/logs", &VARIABLES).unwrap(), "/home/web/logs");
+        assert_eq!(expand("a~", &VARIABLES).unwrap(), "a~");
+    }
+    #[test]
+    fn kept() {
+        for value in ["echo $$ $! $HOME", "date +%s", "100%", "$", "~user"] {
+            assert_eq!(expand(value, &VARIABLES).unwrap(), value);
+        }
+        assert_eq!(
+            expand("$${HOME} %%(here)s", &VARIABLES).unwrap(),
+            "${HOME} %(here)s"
+        );
+    }
+    #[test]
+    fn errors() {
+        for value in [
+            "${TASKMASTER_TEST_UNSET}",
+            "${HOME",
+            "%(nope)s",
+            "%(program_name)d",
+            "%(here)",
+        ] {
+            assert!(expand(value, &VARIABLES).is_err(), "{value}");
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use super::{
+    child::{ProcessState, Status},
+    event::Event,
+    orphan, Program,
+};
+use crate::config::Signal;
+use std::{
+    error::Error,
+    process::{self, Command, Stdio},
+};
+
+/// The commands a program can run on the state changes of its childs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    Start,
+    Exit,
+    UnexpectedExit,
+    Fatal,
+}
+
+impl Hook {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::Start => "on_start",
+            Hook::Exit => "on_exit",
+            Hook::UnexpectedExit => "on_unexpected_exit",
+            Hook::Fatal => "on_fatal",
+        }
+    }
+
+    /// the hooks triggered by a state change
+    pub fn triggered(event: &Event) -> Vec<Hook> {
+        let mut hooks = Vec::new();
+        if event.to == ProcessState::Starting {
+            hooks.push(Hook::Start);
+        }
+        if event.is_exit() {
+            hooks.push(Hook::Exit);
+            if !event.expected {
+                hooks.push(Hook::UnexpectedExit);
+            }
+        }
+        if event.to == ProcessState::Fatal {
+            hooks.push(Hook::Fatal);
+        }
+        hooks
+    }
+
+    fn command<'a>(&self, program: &'a Program) -> Option<&'a String> {
+        match self {
+            Hook::Start => program.on_start.as_ref(),
+            Hook::Exit => program.on_exit.as_ref(),
+            Hook::UnexpectedExit => program.on_unexpected_exit.as_ref(),
+            Hook::Fatal => program.on_fatal.as_ref(),
+        }
+    }
+
+    /// run the hook command with `sh -c`, if the program has one
+    pub fn spawn(
+        &self,
+        program: &Program,
+        event: &Event,
+    ) -> Result<Option<process::Child>, Box<dyn Error>> {
+        let Some(command) = self.command(program) else {
+            return Ok(None);
+        };
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .envs(environment(self, event));
+        if let Some(cwd) = &program.cwd {
+            cmd.current_dir(cwd);
+        }
+        Ok(Some(cmd.spawn()?))
+    }
+}
+
+/// the variables describing the state change, given to the hook commands
+fn environment(hook: &Hook, event: &Event) -> Vec<(&'static str, String)> {
+    let mut env = vec![
+        (orphan::HOOK_ENV, hook.name().to_string()),
+        ("TASKMASTER_PROGRAM_NAME", event.program.clone()),
+        ("TASKMASTER_PROCESS_NAME", event.process.clone()),
+        ("TASKMASTER_PID", event.pid.to_string()),
+        ("TASKMASTER_RESTART_COUNT", event.restarts.to_string()),
+        ("TASKMASTER_FROM_STATE", event.from.name().to_string()),
+        ("TASKMASTER_STATE", event.to.name().to_string()),
+    ];
+    match event.status {
+        Status::Finished(_, code) => env.push(("TASKMASTER_EXIT_CODE", code.to_string())),
+        Status::Terminated(_, signal) => env.push((
+            "TASKMASTER_SIGNAL",
+            Signal::try_from(signal)
+                .map(|s| format!("{s:?}"))
+                .unwrap_or(signal.to_string()),
+        )),
+        _ => (),
+    }
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{environment, Hook};
+    use crate::program::{
+        child::{ProcessState, Status},
+        event::Event,
+    };
+    use std::time::Instant;
+
+    fn event(from: ProcessState, to: ProcessState, status: Status, expected: bool) -> Event {
+        Event {
+            program: "web".to_string(),
+            process: "web".to_string(),
+            from,
+            to,
+            status,
+            pid: 42,
+            restarts: 3,
+            expected,
+        }
+    }
+
+    #[test]
+    fn triggered() {
+        let now = Instant::now();
+        let start = event(
+            ProcessState::Backoff,
+            ProcessState::Starting,
+            Status::Starting(now),
+            true,
+        );
+        assert_eq!(Hook::triggered(&start), [Hook::Start]);
+        let exit = event(
+            ProcessState::Running,
+            ProcessState::Exited,
+            Status::Finished(now, 0),
+            true,
+        );
+        assert_eq!(Hook::triggered(&exit), [Hook::Exit]);
+        let crash = event(
+            ProcessState::Starting,
+            ProcessState::Fatal,
+            Status::Terminated(now, 11),
+            false,
+        );
+        assert_eq!(
+            Hook::triggered(&crash),
+            [Hook::Exit, Hook::UnexpectedExit, Hook::Fatal]
+        );
+    }
+    #[test]
+    fn env() {
+        let crash = event(
+            ProcessState::Running,
+            ProcessState::Backoff,
+            Status::Terminated(Instant::now(), 9),
+            false,
+        );
+        let env = environment(&Hook::UnexpectedExit, &crash);
+        assert!(env.contains(&("TASKMASTER_HOOK", "on_unexpected_exit".to_string())));
+        assert!(env.contains(&("TASKMASTER_PID", "42".to_string())));
+        assert!(env.contains(&("TASKMASTER_RESTART_COUNT", "3".to_string())));
+        assert!(env.contains(&("TASKMASTER_SIGNAL", "SIGKILL".to_string())));
+        assert!(!env.iter().any(|(k, _)| *k == "TASKMASTER_EXIT_CODE"));
+    }
+}
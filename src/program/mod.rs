@@ -1,23 +1,51 @@
 pub mod child;
+pub mod event;
+pub mod expand;
+pub mod hook;
+pub mod orphan;
+pub mod readiness;
+pub mod socket;
 
-use crate::config::Signal;
-use child::Child;
-use serde::Deserialize;
+use crate::{config::Signal, notify};
+use child::{Child, ProcessState, Status};
+use event::{Event, Listener, ListenerState};
+use expand::{Expanded, Variables};
+use hook::Hook;
+use orphan::Orphan;
+use readiness::NotifySocket;
+use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DurationSeconds};
+use socket::BoundSocket;
 use std::{
-    collections::HashMap,
-    env::current_dir,
+    collections::{HashMap, VecDeque},
+    env::{self, current_dir},
     error::Error,
     fs::{self, File, OpenOptions},
+    io::Write,
     mem,
-    os::unix::process::CommandExt,
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::process::CommandExt,
+    },
     path::{Path, PathBuf},
     process::{self, Command, Stdio},
-    time::Duration,
+    time::{Duration, Instant},
 };
-use tracing::{debug, error, info, instrument, trace};
+use tracing::{debug, error, info, instrument, trace, warn};
+use users::os::unix::UserExt;
 
-#[derive(Deserialize, Debug, Default, PartialEq, Clone, Eq)]
+/// events waiting for a busy listener, the oldest are dropped past this
+const EVENT_BUFFER_SIZE: usize = 100;
+/// how often the connections of an on-demand program are counted, for idle_timeout
+const IDLE_CHECK: Duration = Duration::from_secs(1);
+/// the index of the child among the processes of its program
+pub const PROCESS_NUM_ENV: &str = "TASKMASTER_PROCESS_NUM";
+/// how many times this process was restarted
+pub const RESTART_COUNT_ENV: &str = "TASKMASTER_RESTART_COUNT";
+/// the pid of taskmaster itself
+pub const SERVER_PID_ENV: &str = "TASKMASTER_SERVER_PID";
+
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Clone, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum RestartPolicy {
     #[default]
@@ -26,16 +54,30 @@ pub enum RestartPolicy {
     UnexpectedExit,
 }
 
-#[derive(Deserialize, Debug, Default, PartialEq, Clone, Eq)]
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Clone, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum StartPolicy {
     #[default]
     Auto,
     Manual,
+    /// started by the first connection to its sockets
+    #[serde(rename = "on-demand")]
+    OnDemand,
+}
+
+/// when a starting child is considered running
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Clone, Copy, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Readiness {
+    /// once it ran for `min_runtime`
+    #[default]
+    MinRuntime,
+    /// once it sent READY=1 on its NOTIFY_SOCKET
+    Notify,
 }
 
 #[serde_as]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Program {
     // Mandatory
     #[serde(rename = "command")]
@@ -53,6 +95,11 @@ pub struct Program {
     pub min_runtime: Duration,
     #[serde(default)]
     pub valid_exit_codes: Vec<i32>,
+    /// with `notify`, the childs are given a NOTIFY_SOCKET like systemd services.
+    /// `WATCHDOG_USEC` in `env` also gives them one, and they are restarted when they stop
+    /// sending WATCHDOG=1
+    #[serde(default)]
+    pub ready: Readiness,
     #[serde(default)]
     pub restart_policy: RestartPolicy,
     #[serde(default = "default_max_restarts")]
@@ -63,6 +110,12 @@ pub struct Program {
     #[serde(default = "default_timeout")]
     #[serde_as(as = "DurationSeconds<u64>")]
     pub graceful_timeout: Duration,
+    /// send the stop signal to the whole process group of the childs. implies killasgroup
+    #[serde(default)]
+    pub stopasgroup: bool,
+    /// send the SIGKILL after graceful_timeout to the whole process group of the childs
+    #[serde(default)]
+    pub killasgroup: bool,
     pub stdin: Option<PathBuf>,
     pub stderr: Option<PathBuf>,
     pub stdout: Option<PathBuf>,
@@ -77,12 +130,65 @@ pub struct Program {
     pub cwd: Option<PathBuf>,
     pub umask: Option<u32>,
     pub user: Option<String>,
+    /// event types sent to the program on its stdin, like a supervisord event listener.
+    /// `PROCESS_STATE` for all the state changes, or `PROCESS_STATE_EXITED` for one of them
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// shell commands run when a child starts, exits, exits unexpectedly or gives up restarting.
+    /// the state change is described by `TASKMASTER_*` environment variables
+    pub on_start: Option<String>,
+    pub on_exit: Option<String>,
+    pub on_unexpected_exit: Option<String>,
+    pub on_fatal: Option<String>,
+    /// listening sockets bound by taskmaster and shared by the childs, like with the socket
+    /// activation of systemd: `tcp:<host>:<port>` or `unix:<path>`
+    #[serde(default)]
+    pub sockets: Vec<String>,
+    /// with `start_policy = "on-demand"`, the childs are stopped once they had no open
+    /// connection for this long
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub idle_timeout: Option<Duration>,
+    /// programs started before this one, once they are running, and stopped after it
+    #[serde(default)]
+    pub depends_on: Vec<String>,
 
     // runtime only
     #[serde(skip)]
     pub childs: Vec<Child>,
     #[serde(skip)]
     pub force_restart: bool,
+    /// started once the programs of `depends_on` are running
+    #[serde(skip)]
+    pub waits_dependencies: bool,
+    /// stopped on request: an on-demand program isn't started by connections until started again
+    #[serde(skip)]
+    pub stopped: bool,
+    /// state changes of the childs, not yet dispatched to the listeners
+    #[serde(skip)]
+    pub transitions: Vec<Event>,
+    /// events waiting for a ready listener process, with their serial
+    #[serde(skip)]
+    pub event_queue: VecDeque<(usize, Event)>,
+    #[serde(skip)]
+    pool_serial: usize,
+    /// running hook commands, waited for in tick
+    #[serde(skip)]
+    hooks: Vec<process::Child>,
+    /// descendants of the childs which outlived their parent
+    #[serde(skip)]
+    pub orphans: Vec<Orphan>,
+    /// the sockets, bound when the first child starts, and kept across restarts and updates
+    #[serde(skip)]
+    pub bound: Vec<BoundSocket>,
+    /// the directory of the configuration file, for `%(here)s`
+    #[serde(skip)]
+    pub here: PathBuf,
+    /// the last time a connection to the sockets was seen, and the last time they were checked
+    #[serde(skip, default = "Instant::now")]
+    last_active: Instant,
+    #[serde(skip, default = "Instant::now")]
+    last_checked: Instant,
 }
 fn default_processes() -> u8 {
     1
@@ -111,8 +217,8 @@ fn is_our_fd(path: impl AsRef<Path>) -> Result<bool, Box<dyn Error>> {
 }
 
 impl Program {
-    #[instrument(skip_all)]
-    fn create_child(&mut self) -> Result<Child, Box<dyn Error>> {
+    #[instrument(skip_all, fields(index = index))]
+    fn create_child(&mut self, index: usize, restarts: usize) -> Result<Child, Box<dyn Error>> {
         let setup_io = |path: Option<&Path>, file_options: &mut OpenOptions| {
             path.map_or(Ok::<Stdio, Box<dyn Error>>(Stdio::null()), |path| {
                 let f = file_options
@@ -127,6 +233,7 @@ impl Program {
                 Ok(f)
             })
         };
+        let expanded = self.expanded(index)?;
         trace!(name = self.name, "where" = ?self.stdin, "Setting up stdin");
         let stdin = setup_io(
             self.stdin.as_deref(),
@@ -134,57 +241,114 @@ impl Program {
         )?;
         trace!(
             name = self.name,
-            "where" = ?self.stdout,
+            "where" = ?expanded.stdout,
             "Setting up stdout"
         );
         let stdout = setup_io(
-            self.stdout.as_deref(),
+            expanded.stdout.as_deref(),
             File::options()
                 .append(true)
                 .truncate(self.stdout_truncate)
                 .create(true),
         )?;
-        trace!(name = self.name, "where" = ?self.stderr, "Setting up stderr");
+        trace!(name = self.name, "where" = ?expanded.stderr, "Setting up stderr");
         let stderr = setup_io(
-            self.stderr.as_deref(),
+            expanded.stderr.as_deref(),
             File::options()
                 .append(true)
                 .truncate(self.stderr_truncate)
                 .create(true),
         )?;
+        let (stdin, stdout) = match self.is_listener() {
+            true => (Stdio::piped(), Stdio::piped()),
+            false => (stdin, stdout),
+        };
         trace!(name = self.name, "Setting up stdio done");
 
         let mut env_vars = HashMap::new();
-        for entry in self.env.clone() {
+        for entry in expanded.env {
             let parts = entry
                 .split_once('=')
                 .ok_or(format!("Invalid env var: {entry}"))?;
             env_vars.insert(parts.0.to_string(), parts.1.to_string());
         }
+        env_vars.insert(orphan::PROGRAM_ENV.to_string(), self.name.clone());
+        env_vars.insert(PROCESS_NUM_ENV.to_string(), index.to_string());
+        env_vars.insert(RESTART_COUNT_ENV.to_string(), restarts.to_string());
+        env_vars.insert(SERVER_PID_ENV.to_string(), process::id().to_string());
+        let notify = match self.ready == Readiness::Notify || self.watchdog().is_some() {
+            true => {
+                Some(NotifySocket::new().map_err(|e| format!("creating the notify socket: {e}"))?)
+            }
+            false => None,
+        };
+        if let Some(notify) = &notify {
+            env_vars.insert(notify::SOCKET_ENV.to_string(), notify.address.clone());
+        }
+        self.listen()?;
+        if !self.bound.is_empty() {
+            env_vars.insert(
+                socket::LISTEN_FDS_ENV.to_string(),
+                self.bound.len().to_string(),
+            );
+        }
 
-        let cwd = self.cwd.clone().unwrap_or(
+        let cwd = expanded.cwd.unwrap_or(
             current_dir().map_err(|e| format!("couldn't get the current directory: {e}"))?,
         );
         let previous_umask = self.umask.map(|m| unsafe { libc::umask(m) });
-        let mut command = Command::new(&self.cmd);
+        let mut command = match self.bound.is_empty() {
+            true => Command::new(&expanded.cmd),
+            false => {
+                let mut shim = Command::new("sh");
+                shim.arg("-c")
+                    .arg(socket::LISTEN_PID_SHIM)
+                    .arg(&expanded.cmd);
+                let mut fds: Vec<_> = self.bound.iter().map(BoundSocket::fd).collect();
+                unsafe { shim.pre_exec(move || socket::install(&mut fds)) };
+                shim
+            }
+        };
         let cmd = command
             .stdin(stdin)
             .stdout(stdout)
             .stderr(stderr)
-            .args(self.args.clone())
+            .args(expanded.args)
+            // meant for taskmaster only
+            .env_remove(notify::SOCKET_ENV)
+            .env_remove(notify::WATCHDOG_USEC_ENV)
+            .env_remove(notify::WATCHDOG_PID_ENV)
+            .env_remove(socket::LISTEN_FDS_ENV)
+            .env_remove(socket::LISTEN_PID_ENV)
             .envs(env_vars)
-            .current_dir(cwd);
+            .current_dir(cwd)
+            // its own process group, to be able to signal its descendants too
+            .process_group(0);
         if let Some(user) = &self.user {
             let u = users::get_user_by_name(user).ok_or(format!("User `{user}` not found"))?;
             let uid = u.uid();
             cmd.uid(uid).gid(u.primary_group_id());
         }
-        let child = cmd.spawn()?;
+        let child = cmd.spawn();
         if let Some(umask) = previous_umask {
             unsafe { libc::umask(umask) };
         }
-        debug!(pid = child.id(), name = self.name, "Running");
-        Ok(Child::new(child))
+        let child = child?;
+        debug!(pid = child.id(), name = self.name, index, "Running");
+        let mut child = Child::new(child, index);
+        child.notify = notify;
+        if let Some(stdout) = child.process.stdout() {
+            let fd = stdout.as_raw_fd();
+            unsafe {
+                libc::fcntl(
+                    fd,
+                    libc::F_SETFL,
+                    libc::fcntl(fd, libc::F_GETFL) | libc::O_NONBLOCK,
+                )
+            };
+            child.listener = Some(Listener::default());
+        }
+        Ok(child)
     }
 
     #[instrument(skip_all)]
@@ -194,10 +358,13 @@ impl Program {
         } else {
             return Err("Some processes are still running".into());
         }
+        self.stopped = false;
         info!(name = self.name, "starting process...");
         debug!(name = self.name, cmd = ?self.cmd, args = ?self.args, env = ?self.env);
-        for _ in 0..self.processes {
-            let child = self.create_child()?;
+        self.last_active = Instant::now();
+        for index in 0..self.processes as usize {
+            let child = self.create_child(index, 0)?;
+            self.record_transition(&child, ProcessState::Stopped);
             self.childs.push(child);
         }
         info!(
@@ -207,9 +374,127 @@ impl Program {
         Ok(())
     }
 
+    /// the command, environment and files of the process number `index`, with the variables
+    /// of its configuration expanded
+    pub fn expanded(&self, index: usize) -> Result<Expanded, String> {
+        let home = match &self.user {
+            Some(user) => users::get_user_by_name(user).map(|u| u.home_dir().to_path_buf()),
+            None => env::var_os("HOME").map(PathBuf::from),
+        };
+        let home = home.map(|h| h.display().to_string());
+        let here = self.here.display().to_string();
+        let variables = Variables {
+            program_name: &self.name,
+            process_num: index,
+            here: &here,
+            home: home.as_deref(),
+        };
+        let expand = |value: &str| expand::expand(value, &variables);
+        let path = |path: &Option<PathBuf>| {
+            path.as_ref()
+                .map(|p| expand(&p.display().to_string()).map(PathBuf::from))
+                .transpose()
+        };
+        Ok(Expanded {
+            cmd: PathBuf::from(expand(&self.cmd.display().to_string())?),
+            args: self
+                .args
+                .iter()
+                .map(|a| expand(a))
+                .collect::<Result<_, _>>()?,
+            env: self
+                .env
+                .iter()
+                .map(|e| expand(e))
+                .collect::<Result<_, _>>()?,
+            cwd: path(&self.cwd)?,
+            stdout: path(&self.stdout)?,
+            stderr: path(&self.stderr)?,
+        })
+    }
+
+    /// bind the sockets, if they aren't already
+    pub fn listen(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.bound.is_empty() {
+            self.bound = self
+                .sockets
+                .iter()
+                .map(|address| {
+                    BoundSocket::bind(address).map_err(|e| format!("binding {address}: {e}"))
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        Ok(())
+    }
+
+    /// an on-demand program waiting for its first connection
+    pub fn waits_connection(&self) -> bool {
+        self.start_policy == StartPolicy::OnDemand
+            && self.all_stopped()
+            && !self.force_restart
+            && !self.stopped
+    }
+
+    /// as an on-demand program, start once a connection is waiting on the sockets
+    pub fn start_on_demand(&mut self) {
+        if !self.waits_connection() || !self.bound.iter().any(BoundSocket::pending) {
+            return;
+        }
+        info!(name = self.name, "Connection received");
+        if let Err(e) = self.start() {
+            error!(error = e, name = self.name, "starting program on demand");
+            // or they would start it again right away
+            self.bound.iter().for_each(BoundSocket::refuse);
+        }
+    }
+
+    /// take back the childs left running by a previous taskmaster, and start the missing ones
+    pub fn readopt(&mut self, mut adopted: Vec<Child>) -> Result<(), Box<dyn Error>> {
+        self.childs.clear();
+        for index in 0..self.processes as usize {
+            match adopted.iter().position(|c| c.index == index) {
+                Some(position) => self.childs.push(adopted.swap_remove(position)),
+                None => {
+                    let child = self.create_child(index, 0)?;
+                    self.record_transition(&child, ProcessState::Stopped);
+                    self.childs.push(child);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// start a single process of the program, keeping its place among the childs.
+    /// the restarts count of this process is reset
+    pub fn start_child(&mut self, index: usize) -> Result<(), Box<dyn Error>> {
+        match self.childs.get(index) {
+            Some(child) if child.status.is_running() => {
+                return Err("The process is still running".into())
+            }
+            Some(_) => (),
+            None => return Err(format!("No process number {index}").into()),
+        }
+        self.stopped = false;
+        info!(name = self.name, index, "starting process...");
+        let from = self.childs[index].state(self);
+        let child = self.create_child(index, 0)?;
+        self.record_transition(&child, from);
+        self.childs[index] = child;
+        Ok(())
+    }
+
+    /// name of the process number `index`, as seen by supervisord clients
+    pub fn process_name(&self, index: usize) -> String {
+        match self.processes {
+            1 => self.name.clone(),
+            _ => format!("{}_{index}", self.name),
+        }
+    }
+
     /// Kill the program and all its children. for graceful shutdown, check stop().
     #[instrument(skip_all)]
     pub fn kill(&mut self) {
+        let group = self.kill_as_group();
         for child in &mut self.childs {
             if child.status.is_running() {
                 debug!(
@@ -218,33 +503,71 @@ impl Program {
                     signal = %self.stop_signal,
                     "Killing"
                 );
-                let _ = child.process.kill();
+                child.kill(group);
             }
         }
+        for orphan in &mut self.orphans {
+            debug!(pid = orphan.pid, name = self.name, "Killing orphan");
+            orphan.kill();
+        }
     }
 
     /// start the graceful shutdown of the childs: send the stop signal, and mark them as stopping
     #[instrument(skip_all)]
     pub fn stop(&mut self) {
-        for child in &mut self.childs {
-            if child.status.is_running() {
-                debug!(
-                    pid = child.process.id(),
-                    name = self.name,
-                    signal = %self.stop_signal,
-                    "Killing"
-                );
-                child.stop(self.stop_signal as i32);
-            }
+        self.waits_dependencies = false;
+        self.stopped = true;
+        for index in 0..self.childs.len() {
+            self.stop_child(index);
+        }
+        for orphan in &mut self.orphans {
+            debug!(pid = orphan.pid, name = self.name, "Stopping orphan");
+            orphan.stop(self.stop_signal as i32);
+        }
+    }
+    /// start the graceful shutdown of a single child, or cancel its restart
+    pub fn stop_child(&mut self, index: usize) {
+        let Some(child) = self.childs.get(index) else {
+            return;
+        };
+        if matches!(child.status, Status::Starting(_) | Status::Running(_)) {
+            debug!(
+                pid = child.process.id(),
+                name = self.name,
+                signal = %self.stop_signal,
+                "Killing"
+            );
+        }
+        let from = child.state(self);
+        self.childs[index].stop(self.stop_signal as i32, self.stopasgroup);
+        if let Some(event) = Event::transition(self, &self.childs[index], from) {
+            self.push_transition(event);
         }
     }
     /// mark the program to be restarted
     pub fn restart(&mut self) {
         self.force_restart = true;
         self.stop();
+        self.stopped = false;
     }
     /// this need to be called regularly, to check the status of the program and its children.
     pub fn tick(&mut self) -> Result<(), Box<dyn Error>> {
+        let finished_before = self.all_stopped();
+        let mut childs = mem::take(&mut self.childs);
+        for child in &mut childs {
+            let _ = child.tick(self);
+            if let (Some(listener), Some(stdout)) = (&mut child.listener, child.process.stdout()) {
+                if listener.closed {
+                    continue;
+                }
+                if let Some(event) = listener.read(stdout) {
+                    self.event_queue.push_front(event);
+                }
+            }
+        }
+        self.childs = childs;
+        self.tick_orphans();
+        // once the last child of a restarted program exited
         if self.force_restart && self.all_stopped() {
             self.force_restart = false;
             self.childs.clear();
@@ -252,12 +575,9 @@ impl Program {
                 error!(error = e, name = self.name, "Restarting");
             };
         }
-        let finished_before = self.all_stopped();
-        let mut childs = mem::take(&mut self.childs);
-        for child in &mut childs {
-            let _ = child.tick(self);
-        }
-        self.childs = childs;
+        self.tick_idle();
+        self.send_events();
+        self.wait_hooks();
         if !finished_before && self.all_stopped() {
             info!(
                 name = self.name,
@@ -266,28 +586,339 @@ impl Program {
         }
         Ok(())
     }
-    /// apply a new configuration to the program, and restart it if needed
+    /// the next time the connections of a running on-demand program are checked
+    fn idle_check(&self) -> Option<Instant> {
+        let idle_timeout = self
+            .idle_timeout
+            .filter(|_| self.start_policy == StartPolicy::OnDemand)?;
+        if !self
+            .childs
+            .iter()
+            .any(|c| matches!(c.status, Status::Starting(_) | Status::Running(_)))
+        {
+            return None;
+        }
+        Some((self.last_checked + IDLE_CHECK).min(self.last_active + idle_timeout))
+    }
+    /// stop an on-demand program which had no connection for idle_timeout. the connections
+    /// are counted every second: shorter ones can be missed
+    fn tick_idle(&mut self) {
+        let (Some(check), Some(idle_timeout)) = (self.idle_check(), self.idle_timeout) else {
+            return;
+        };
+        let now = Instant::now();
+        if check > now {
+            return;
+        }
+        self.last_checked = now;
+        let active = self
+            .bound
+            .iter()
+            .any(|socket| socket.pending() || socket.connections().map_or(true, |n| n > 0));
+        if active {
+            self.last_active = now;
+        } else if now >= self.last_active + idle_timeout {
+            info!(name = self.name, "Idle, stopping until the next connection");
+            self.stop();
+            self.stopped = false;
+        }
+    }
+    /// apply a new configuration to the program, and tell if it needs a restart
     #[instrument(skip_all)]
-    pub fn update(&mut self, new: Program) {
+    pub fn update(&mut self, new: Program) -> bool {
+        // only used by the next start and stop, the processes don't need a restart
+        self.depends_on.clone_from(&new.depends_on);
         if self == &new {
             trace!(
                 name = self.name,
                 "Not updating: configuration didn't change"
             );
-            return;
+            return false;
         }
         debug!(
             name = self.name,
             "Updating configuration, restartings processes"
         );
         let childs = mem::take(&mut self.childs);
+        let event_queue = mem::take(&mut self.event_queue);
+        let hooks = mem::take(&mut self.hooks);
+        let orphans = mem::take(&mut self.orphans);
+        // the connections waiting on the sockets aren't dropped
+        let bound = mem::take(&mut self.bound);
+        let sockets_changed = self.sockets != new.sockets;
+        let stopped = self.stopped;
         let _ = mem::replace(self, new);
         self.childs = childs;
-        self.restart();
+        self.event_queue = event_queue;
+        self.hooks = hooks;
+        self.orphans = orphans;
+        self.stopped = stopped;
+        // the old sockets are closed before the new ones are bound, on the same addresses
+        match sockets_changed {
+            true => drop(bound),
+            false => self.bound = bound,
+        }
+        if self.start_policy == StartPolicy::OnDemand {
+            if let Err(e) = self.listen() {
+                error!(error = e, name = self.name, "listening");
+            }
+            // started by the next connection
+            if self.all_stopped() {
+                return false;
+            }
+        }
+        true
     }
     /// if all the children are stopped or finished
     pub fn all_stopped(&self) -> bool {
-        self.childs.iter().all(|c| !c.status.is_running())
+        self.childs.iter().all(|c| !c.status.is_running()) && self.orphans.is_empty()
+    }
+
+    /// if the final SIGKILL is sent to the process groups of the childs
+    pub fn kill_as_group(&self) -> bool {
+        self.killasgroup || self.stopasgroup
+    }
+
+    /// if the process is a child, a hook or an orphan of this program
+    pub fn owns(&self, pid: i32) -> bool {
+        let pid = pid as u32;
+        self.childs
+            .iter()
+            .any(|c| !c.reaped && c.process.id() == pid)
+            || self.hooks.iter().any(|h| h.id() == pid)
+            || self.orphans.iter().any(|o| o.pid as u32 == pid)
+    }
+
+    /// take care of a descendant reparented to taskmaster
+    pub fn adopt(&mut self, pid: i32) {
+        info!(name = self.name, pid, "Adopting orphaned process");
+        let mut orphan = Orphan::new(pid);
+        if self
+            .childs
+            .iter()
+            .any(|c| matches!(c.status, Status::Terminating(_)))
+        {
+            orphan.stop(self.stop_signal as i32);
+        }
+        self.orphans.push(orphan);
+    }
+
+    /// reap the orphans, and kill the ones which didn't stop in time
+    fn tick_orphans(&mut self) {
+        let name = &self.name;
+        let graceful_timeout = self.graceful_timeout;
+        self.orphans.retain_mut(|orphan| {
+            if orphan.try_wait() {
+                return false;
+            }
+            if orphan
+                .deadline(graceful_timeout)
+                .is_some_and(|d| d <= Instant::now())
+            {
+                warn!(
+                    name,
+                    pid = orphan.pid,
+                    "graceful shutdown timeout, killing the orphan"
+                );
+                orphan.kill();
+            }
+            true
+        });
+    }
+    /// save the state change of a child for the event listeners
+    pub fn record_transition(&mut self, child: &Child, from: ProcessState) {
+        if let Some(event) = Event::transition(self, child, from) {
+            self.push_transition(event);
+        }
+    }
+    fn push_transition(&mut self, event: Event) {
+        trace!(name = self.name, event = event.name(), "State change");
+        for hook in Hook::triggered(&event) {
+            match hook.spawn(self, &event) {
+                Ok(Some(child)) => {
+                    debug!(
+                        name = self.name,
+                        hook = hook.name(),
+                        pid = child.id(),
+                        "Running hook"
+                    );
+                    self.hooks.push(child);
+                }
+                Ok(None) => (),
+                Err(e) => error!(
+                    name = self.name,
+                    hook = hook.name(),
+                    error = e,
+                    "running hook"
+                ),
+            }
+        }
+        self.transitions.push(event);
+    }
+
+    /// the watchdog timeout the program asks for with WATCHDOG_USEC in its environment
+    pub fn watchdog(&self) -> Option<Duration> {
+        self.env
+            .iter()
+            .find_map(|var| {
+                var.strip_prefix(notify::WATCHDOG_USEC_ENV)?
+                    .strip_prefix('=')?
+                    .parse()
+                    .ok()
+            })
+            .filter(|&usec| usec > 0)
+            .map(Duration::from_micros)
+    }
+
+    /// no listener process is waiting for the result of an event
+    pub fn listeners_idle(&self) -> bool {
+        self.childs.iter().all(|c| {
+            !matches!(
+                c.listener.as_ref().map(|l| &l.state),
+                Some(ListenerState::Busy(..))
+            )
+        })
+    }
+
+    pub fn is_listener(&self) -> bool {
+        !self.events.is_empty()
+    }
+
+    /// queue the event for the listener processes, if the program is subscribed to it
+    pub fn queue_event(&mut self, serial: usize, event: &Event) {
+        if !self.events.iter().any(|e| event.matches(e)) {
+            return;
+        }
+        if self.event_queue.len() >= EVENT_BUFFER_SIZE {
+            warn!(
+                name = self.name,
+                "Event buffer full, dropping the oldest event"
+            );
+            self.event_queue.pop_front();
+        }
+        self.event_queue.push_back((serial, event.clone()));
+    }
+
+    /// the next time tick has something to do without a child exiting
+    pub fn deadline(&self) -> Option<Instant> {
+        let orphans = self
+            .orphans
+            .iter()
+            .filter_map(|o| o.deadline(self.graceful_timeout));
+        self.childs
+            .iter()
+            .filter_map(|c| c.deadline(self))
+            .chain(orphans)
+            .chain(self.idle_check())
+            .min()
+    }
+
+    /// the file descriptors tick reads from: the stdout of the event listeners, the pidfds
+    /// of the adopted processes, and the notify sockets
+    pub fn fds(&self) -> Vec<RawFd> {
+        self.childs
+            .iter()
+            .flat_map(|c| {
+                let stdout = match &c.listener {
+                    Some(listener) if !listener.closed => c.process.pipes().1,
+                    _ => None,
+                };
+                let pidfd = c.process.pidfd().filter(|_| c.status.is_running());
+                let notify = c.notify.as_ref().filter(|_| c.status.is_running());
+                stdout
+                    .into_iter()
+                    .chain(pidfd)
+                    .chain(notify.map(NotifySocket::fd))
+            })
+            .collect()
+    }
+
+    /// the sockets of an on-demand program waiting for its first connection
+    pub fn demand_fds(&self) -> Vec<RawFd> {
+        match self.waits_connection() {
+            true => self.bound.iter().map(BoundSocket::fd).collect(),
+            false => Vec::new(),
+        }
+    }
+
+    /// reap the finished hook commands
+    fn wait_hooks(&mut self) {
+        let name = &self.name;
+        self.hooks.retain_mut(|hook| match hook.try_wait() {
+            Ok(Some(status)) if !status.success() => {
+                warn!(name, pid = hook.id(), %status, "hook failed");
+                false
+            }
+            Ok(Some(_)) => false,
+            Ok(None) => true,
+            Err(e) => {
+                warn!(name, error = %e, "waiting for hook");
+                false
+            }
+        });
+    }
+
+    /// send the queued events to the listener processes which are ready
+    pub fn send_events(&mut self) {
+        for child in &mut self.childs {
+            let Some(listener) = &mut child.listener else {
+                continue;
+            };
+            if !matches!(listener.state, ListenerState::Ready) || !child.status.is_running() {
+                continue;
+            }
+            let (Some(stdin), Some((serial, event))) =
+                (child.process.stdin(), self.event_queue.pop_front())
+            else {
+                continue;
+            };
+            self.pool_serial += 1;
+            let payload = event.payload();
+            let message = format!(
+                "ver:3.0 server:taskmaster serial:{serial} pool:{} poolserial:{} eventname:{} len:{}\n{payload}",
+                self.name,
+                self.pool_serial,
+                event.name(),
+                payload.len()
+            );
+            match stdin.write_all(message.as_bytes()) {
+                Ok(()) => {
+                    trace!(name = self.name, serial, "Event sent");
+                    listener.state = ListenerState::Busy(serial, event);
+                }
+                Err(e) => {
+                    warn!(name = self.name, error = %e, "sending the event to the listener");
+                    self.event_queue.push_front((serial, event));
+                }
+            }
+        }
+    }
+
+    /// one line per child, describing its status. used by the `status` command
+    pub fn report(&self) -> String {
+        if self.childs.is_empty() && self.waits_connection() {
+            return format!("{}\tWaiting for a connection", self.name);
+        }
+        if self.childs.is_empty() && self.waits_dependencies {
+            return format!("{}\tWaiting for its dependencies", self.name);
+        }
+        if self.childs.is_empty() {
+            return format!("{}\tNo processes", self.name);
+        }
+        self.childs
+            .iter()
+            .map(|c| {
+                format!(
+                    "{}\t{}\tpid {}\trestarts {}\tsince {:?}",
+                    self.name,
+                    c.status,
+                    c.process.id(),
+                    c.restarts,
+                    c.status.get_instant().elapsed()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
     }
 }
 
@@ -298,6 +929,7 @@ impl PartialEq for Program {
             && self.processes == other.processes
             && self.min_runtime == other.min_runtime
             && self.valid_exit_codes == other.valid_exit_codes
+            && self.ready == other.ready
             && self.max_restarts == other.max_restarts
             && self.stop_signal == other.stop_signal
             && self.graceful_timeout == other.graceful_timeout
@@ -312,13 +944,29 @@ impl PartialEq for Program {
             && self.umask == other.umask
             && self.user == other.user
             && self.start_policy == other.start_policy
+            && self.events == other.events
+            && self.on_start == other.on_start
+            && self.on_exit == other.on_exit
+            && self.on_unexpected_exit == other.on_unexpected_exit
+            && self.on_fatal == other.on_fatal
+            && self.stopasgroup == other.stopasgroup
+            && self.killasgroup == other.killasgroup
+            && self.sockets == other.sockets
+            && self.idle_timeout == other.idle_timeout
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::is_our_fd;
-    use std::process::id;
+    use super::{is_our_fd, ProcessState, Program, Status};
+    use std::{
+        env, fs,
+        io::Read,
+        os::unix::net::UnixStream,
+        process::{id, Command},
+        thread,
+        time::{Duration, Instant},
+    };
 
     #[test]
     #[should_panic]
@@ -371,4 +1019,251 @@ mod tests {
     fn open_basic_config() {
         assert!(!is_our_fd("config/default.toml").unwrap());
     }
+
+    #[test]
+    fn stop_as_group() {
+        let pidfile = env::temp_dir().join(format!("taskmaster-group-{}", id()));
+        let mut program: Program = toml::from_str(&format!(
+            "name = \"group\"\ncommand = \"sh\"\nargs = [\"-c\", \"sleep 30 & echo $! > {}; wait\"]\nstopasgroup = true",
+            pidfile.display()
+        ))
+        .unwrap();
+        program.start().unwrap();
+        let start = Instant::now();
+        let pid = loop {
+            if let Some(pid) = fs::read_to_string(&pidfile)
+                .ok()
+                .and_then(|s| s.trim().parse::<i32>().ok())
+            {
+                break pid;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        };
+        fs::remove_file(&pidfile).unwrap();
+        program.stop();
+        // the sleep is gone, or a zombie if the test process is a subreaper
+        let alive = || {
+            fs::read_to_string(format!("/proc/{pid}/stat")).is_ok_and(|stat| !stat.contains(") Z "))
+        };
+        while alive() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        program.kill();
+    }
+    #[test]
+    fn stopped_not_restarted() {
+        let mut program: Program = toml::from_str(
+            "name = \"always\"\ncommand = \"sleep\"\nargs = [\"30\"]\nrestart_policy = \"always\"",
+        )
+        .unwrap();
+        program.start().unwrap();
+        program.stop();
+        let start = Instant::now();
+        while !program.all_stopped() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            program.tick().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        let child = &program.childs[0];
+        assert!(!child.wants_restart(&program));
+        assert_eq!(child.state(&program), ProcessState::Stopped);
+    }
+    #[test]
+    fn restarted_once_exited() {
+        let mut program: Program =
+            toml::from_str("name = \"restart\"\ncommand = \"sleep\"\nargs = [\"30\"]").unwrap();
+        program.start().unwrap();
+        let pid = program.childs[0].process.id();
+        program.restart();
+        let start = Instant::now();
+        while program.force_restart {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            program.tick().unwrap();
+            // in the tick which reaps the last child, nothing else would wake taskmaster up
+            assert!(!program.all_stopped());
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_ne!(program.childs[0].process.id(), pid);
+        program.kill();
+    }
+    #[test]
+    fn owns_killed_child() {
+        let mut program: Program =
+            toml::from_str("name = \"killed\"\ncommand = \"sleep\"\nargs = [\"30\"]").unwrap();
+        program.start().unwrap();
+        let pid = program.childs[0].process.id() as i32;
+        program.kill();
+        assert!(matches!(program.childs[0].status, Status::Stopped(_)));
+        // not reaped as an orphan once it exited, until the program waits for it
+        let start = Instant::now();
+        while !fs::read_to_string(format!("/proc/{pid}/stat")).is_ok_and(|s| s.contains(") Z ")) {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(program.owns(pid));
+        program.tick().unwrap();
+        assert!(program.childs[0].reaped);
+        assert!(!program.owns(pid));
+        program.tick().unwrap();
+    }
+    #[test]
+    fn notify_ready_and_watchdog() {
+        let mut program: Program = toml::from_str(
+            "name = \"notify\"\ncommand = \"python3\"\nargs = [\"tests/notify.py\"]\nready = \"notify\"\nenv = [\"WATCHDOG_USEC=500000\"]",
+        )
+        .unwrap();
+        program.start().unwrap();
+        let start = Instant::now();
+        let tick = |program: &mut Program| {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            program.tick().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        };
+        tick(&mut program);
+        // not after min_runtime, but once READY=1 is received
+        assert!(matches!(program.childs[0].status, Status::Starting(_)));
+        while matches!(program.childs[0].status, Status::Starting(_)) {
+            tick(&mut program);
+        }
+        assert!(matches!(program.childs[0].status, Status::Running(_)));
+        // restarted once the pings stop, whatever the restart policy
+        while program.childs[0].restarts == 0 {
+            tick(&mut program);
+        }
+        assert!(matches!(program.childs[0].status, Status::Starting(_)));
+        program.kill();
+    }
+    #[test]
+    fn systemd_notify() {
+        if Command::new("systemd-notify")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            eprintln!("systemd-notify isn't installed, skipping");
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let done = dir.path().join("done");
+        // systemd-notify waits for its message to be read, with a barrier
+        let mut program: Program = toml::from_str(&format!(
+            "name = \"notify\"\ncommand = \"sh\"\nargs = [\"-c\", \"systemd-notify --ready; touch {}; exec sleep 30\"]\nready = \"notify\"\nmin_runtime = 30",
+            done.display()
+        ))
+        .unwrap();
+        program.start().unwrap();
+        let start = Instant::now();
+        while matches!(program.childs[0].status, Status::Starting(_)) || !done.exists() {
+            // before the timeout of the barrier
+            assert!(start.elapsed() < Duration::from_secs(3));
+            program.tick().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(matches!(program.childs[0].status, Status::Running(_)));
+        program.kill();
+    }
+    #[test]
+    fn expanded_per_child() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut program: Program = toml::from_str(
+            "name = \"echo\"\ncommand = \"sh\"\nargs = [\"-c\", \"echo %(program_name)s $NUM\"]\nenv = [\"NUM=%(process_num)02d\"]\nprocesses = 2\nstdout = \"%(here)s/%(program_name)s_%(process_num)d.log\"",
+        )
+        .unwrap();
+        program.here = dir.path().to_path_buf();
+        program.start().unwrap();
+        let start = Instant::now();
+        while !program.all_stopped() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            program.tick().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        for (index, output) in ["echo 00\n", "echo 01\n"].iter().enumerate() {
+            let log = dir.path().join(format!("echo_{index}.log"));
+            assert_eq!(&fs::read_to_string(log).unwrap(), output);
+        }
+    }
+    #[test]
+    fn identity_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut program: Program = toml::from_str(
+            "name = \"id\"\ncommand = \"sh\"\nargs = [\"-c\", \"echo $TASKMASTER_PROGRAM_NAME $TASKMASTER_PROCESS_NUM $TASKMASTER_RESTART_COUNT $TASKMASTER_SERVER_PID\"]\nprocesses = 2\nrestart_policy = \"always\"\nmax_restarts = 1\nstdout = \"%(here)s/%(process_num)d.log\"",
+        )
+        .unwrap();
+        program.here = dir.path().to_path_buf();
+        program.start().unwrap();
+        let start = Instant::now();
+        while !program.all_stopped() || program.childs.iter().any(|c| c.restarts < 1) {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            program.tick().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        let pid = std::process::id();
+        for index in 0..2 {
+            let log = fs::read_to_string(dir.path().join(format!("{index}.log"))).unwrap();
+            assert_eq!(log, format!("id {index} 0 {pid}\nid {index} 1 {pid}\n"));
+        }
+    }
+    #[test]
+    fn on_demand() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.sock");
+        // answers one connection, and stays idle
+        let mut program: Program = toml::from_str(&format!(
+            "name = \"lazy\"\ncommand = \"python3\"\nargs = [\"-c\", \"import socket, time; socket.socket(fileno=3).accept()[0].close(); time.sleep(30)\"]\nstart_policy = \"on-demand\"\nidle_timeout = 1\nsockets = [\"unix:{}\"]",
+            path.display(),
+        ))
+        .unwrap();
+        program.listen().unwrap();
+        program.start_on_demand();
+        assert!(program.childs.is_empty());
+        let mut client = UnixStream::connect(&path).unwrap();
+        let start = Instant::now();
+        program.start_on_demand();
+        assert_eq!(program.childs.len(), 1);
+        assert!(program.demand_fds().is_empty());
+        // handed the connection
+        assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+        while !program.all_stopped() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            program.tick().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(program.childs[0].state(&program), ProcessState::Stopped);
+        assert_eq!(program.demand_fds().len(), 1);
+        // stopped on request, until started again
+        program.stop();
+        let _client = UnixStream::connect(&path).unwrap();
+        program.start_on_demand();
+        assert_eq!(program.childs[0].state(&program), ProcessState::Stopped);
+        assert!(program.demand_fds().is_empty());
+        program.start().unwrap();
+        assert!(!program.stopped);
+    }
+    #[test]
+    fn sockets() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let mut program: Program = toml::from_str(&format!(
+            "name = \"activated\"\ncommand = \"sh\"\nargs = [\"-c\", \"echo $LISTEN_FDS $LISTEN_PID $$ $(readlink /proc/$$/fd/3) > {}\"]\nsockets = [\"unix:{}\"]",
+            out.display(),
+            dir.path().join("app.sock").display(),
+        ))
+        .unwrap();
+        program.start().unwrap();
+        let start = Instant::now();
+        while !program.all_stopped() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            program.tick().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        let output = fs::read_to_string(&out).unwrap();
+        let fields: Vec<_> = output.split_whitespace().collect();
+        assert_eq!(fields[0], "1");
+        assert_eq!(fields[1], fields[2]);
+        assert!(fields[3].starts_with("socket:"));
+        assert_eq!(program.bound.len(), 1);
+    }
 }
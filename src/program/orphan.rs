@@ -0,0 +1,170 @@
+use std::{
+    fs, io, process,
+    time::{Duration, Instant},
+};
+use tracing::{debug, error};
+
+/// set on every child, to find the program of the descendants reparented to taskmaster
+pub const PROGRAM_ENV: &str = "TASKMASTER_PROGRAM_NAME";
+/// set on the hook commands, whose descendants don't belong to the program
+pub const HOOK_ENV: &str = "TASKMASTER_HOOK";
+
+/// A descendant of a child, reparented to taskmaster when its parent exited
+#[derive(Debug)]
+pub struct Orphan {
+    pub pid: i32,
+    /// set once the stop signal has been sent
+    pub stopping: Option<Instant>,
+}
+
+impl Orphan {
+    pub fn new(pid: i32) -> Self {
+        Orphan {
+            pid,
+            stopping: None,
+        }
+    }
+
+    /// reap the orphan if it exited
+    pub fn try_wait(&self) -> bool {
+        let mut status = 0;
+        match unsafe { libc::waitpid(self.pid, &mut status, libc::WNOHANG) } {
+            0 => false,
+            -1 => {
+                // not our child anymore
+                debug!(pid = self.pid, error = %io::Error::last_os_error(), "waiting for orphan");
+                true
+            }
+            _ => {
+                debug!(pid = self.pid, status, "orphan exited");
+                true
+            }
+        }
+    }
+
+    pub fn stop(&mut self, signal: i32) {
+        if self.stopping.is_none() {
+            self.signal(signal);
+            self.stopping = Some(Instant::now());
+        }
+    }
+
+    pub fn kill(&mut self) {
+        self.signal(libc::SIGKILL);
+    }
+
+    fn signal(&self, signal: i32) {
+        if unsafe { libc::kill(self.pid, signal) } != 0 {
+            error!(pid = self.pid, signal, "couldn't send signal to the orphan");
+        }
+    }
+
+    /// when the graceful shutdown times out
+    pub fn deadline(&self, graceful_timeout: Duration) -> Option<Instant> {
+        self.stopping.map(|since| since + graceful_timeout)
+    }
+}
+
+/// make the orphaned descendants of the childs be reparented to taskmaster instead of init
+pub fn set_subreaper() -> io::Result<()> {
+    match unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// the pids of the direct children of taskmaster, and if they are zombies
+pub fn children() -> Vec<(i32, bool)> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let ppid = process::id() as i32;
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<i32>().ok())
+        .filter_map(|pid| {
+            let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+            let (state, parent) = parse_stat(&stat)?;
+            (parent == ppid).then_some((pid, state == 'Z'))
+        })
+        .collect()
+}
+
+/// the state and the parent pid from `/proc/<pid>/stat`
+fn parse_stat(stat: &str) -> Option<(char, i32)> {
+    // the command name can contain spaces and parentheses, it ends at the last one
+    let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    let ppid = fields.next()?.parse().ok()?;
+    Some((state, ppid))
+}
+
+/// the program a process has been started for, from its environment. none for the processes
+/// left by a hook
+pub fn owner(pid: i32) -> Option<String> {
+    let environ = fs::read(format!("/proc/{pid}/environ")).ok()?;
+    let prefix = format!("{PROGRAM_ENV}=");
+    let hook = format!("{HOOK_ENV}=");
+    let mut vars = environ.split(|&b| b == 0);
+    if vars.clone().any(|var| var.starts_with(hook.as_bytes())) {
+        return None;
+    }
+    vars.find_map(|var| var.strip_prefix(prefix.as_bytes()))
+        .map(|name| String::from_utf8_lossy(name).to_string())
+}
+
+/// reap a child nobody owns
+pub fn reap(pid: i32) {
+    let mut status = 0;
+    unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{children, owner, parse_stat, set_subreaper, Orphan, HOOK_ENV, PROGRAM_ENV};
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn stat() {
+        assert_eq!(
+            parse_stat("42 (a (b) c) S 1 42 42 0 -1 4194560"),
+            Some(('S', 1))
+        );
+        assert_eq!(parse_stat("42 (zombie) Z 7 42"), Some(('Z', 7)));
+        assert_eq!(parse_stat("garbage"), None);
+    }
+    #[test]
+    fn adopt_grandchild() {
+        set_subreaper().unwrap();
+        let output = Command::new("sh")
+            .args(["-c", "sleep 10 >/dev/null 2>&1 & echo $!"])
+            .env(PROGRAM_ENV, "wrapper")
+            .stdout(Stdio::piped())
+            .output()
+            .unwrap();
+        let pid: i32 = String::from_utf8(output.stdout)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        assert!(children().contains(&(pid, false)));
+        assert_eq!(owner(pid).as_deref(), Some("wrapper"));
+        let mut orphan = Orphan::new(pid);
+        assert!(!orphan.try_wait());
+        orphan.kill();
+        while !orphan.try_wait() {}
+        assert!(!children().iter().any(|&(p, _)| p == pid));
+    }
+    #[test]
+    fn hook_descendant() {
+        let mut child = Command::new("sleep")
+            .arg("10")
+            .env(PROGRAM_ENV, "web")
+            .env(HOOK_ENV, "on_exit")
+            .spawn()
+            .unwrap();
+        assert_eq!(owner(child.id() as i32), None);
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    fmt::Write,
+    io::{self, ErrorKind},
+    mem,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    ptr,
+};
+use tracing::warn;
+
+/// The socket on which a child sends its readiness and watchdog pings, like to systemd
+#[derive(Debug)]
+pub struct NotifySocket {
+    socket: UnixDatagram,
+    /// the value of NOTIFY_SOCKET, in the abstract namespace
+    pub address: String,
+}
+
+impl NotifySocket {
+    /// a new socket, with a random name. the credentials of the senders are received, to only
+    /// accept the messages of the child
+    pub fn new() -> io::Result<Self> {
+        loop {
+            let mut random = [0u8; 16];
+            if unsafe { libc::getrandom(random.as_mut_ptr().cast(), random.len(), 0) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let name = random
+                .iter()
+                .fold("taskmaster/".to_string(), |mut name, b| {
+                    let _ = write!(name, "{b:02x}");
+                    name
+                });
+            let socket = match UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name)?) {
+                Err(e) if e.kind() == ErrorKind::AddrInUse => continue,
+                socket => socket?,
+            };
+            let enable: libc::c_int = 1;
+            let set = unsafe {
+                libc::setsockopt(
+                    socket.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    libc::SO_PASSCRED,
+                    ptr::from_ref(&enable).cast(),
+                    mem::size_of_val(&enable) as libc::socklen_t,
+                )
+            };
+            if set != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            socket.set_nonblocking(true)?;
+            return Ok(NotifySocket {
+                socket,
+                address: format!("@{name}"),
+            });
+        }
+    }
+
+    /// a socket kept open across a re-exec
+    pub fn from_fd(fd: OwnedFd) -> Self {
+        let socket = UnixDatagram::from(fd);
+        let address = socket
+            .local_addr()
+            .ok()
+            .and_then(|a| {
+                a.as_abstract_name()
+                    .map(|n| String::from_utf8_lossy(n).to_string())
+            })
+            .map(|name| format!("@{name}"))
+            .unwrap_or_default();
+        NotifySocket { socket, address }
+    }
+
+    /// the `KEY=value` lines received since the last call, from the processes of the group
+    /// `group`. the others are dropped, as those of a sender which already exited: like
+    /// `systemd-notify`, it can wait for a `BARRIER=1`, whose file descriptors are closed
+    /// once the messages before it are read
+    pub fn read(&self, group: u32) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut buf = [0; 4096];
+        let mut barriers = Vec::new();
+        loop {
+            let received = self.recv(&mut buf).map(|(n, pid, fds)| {
+                barriers.extend(fds);
+                (n, pid)
+            });
+            match received {
+                Ok((n, Some(pid))) if unsafe { libc::getpgid(pid) } == group as libc::pid_t => {
+                    lines.extend(
+                        String::from_utf8_lossy(&buf[..n])
+                            .lines()
+                            .map(ToString::to_string),
+                    )
+                }
+                Ok((_, pid)) => warn!(?pid, group, "Ignoring a notification from another process"),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!(error = %e, "reading the notify socket");
+                    break;
+                }
+            }
+        }
+        // the senders waiting for their messages to be read can exit
+        drop(barriers);
+        lines
+    }
+
+    /// a datagram, the pid of its sender and the file descriptors it sent
+    fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, Option<libc::pid_t>, Vec<OwnedFd>)> {
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr().cast(),
+            iov_len: buf.len(),
+        };
+        // aligned for the cmsghdr, and large enough for the credentials and a few descriptors
+        let mut control = [0u64; 16];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = mem::size_of_val(&control);
+        let flags = libc::MSG_DONTWAIT | libc::MSG_CMSG_CLOEXEC;
+        let n = unsafe { libc::recvmsg(self.socket.as_raw_fd(), &mut msg, flags) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut pid = None;
+        let mut fds = Vec::new();
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        while !cmsg.is_null() {
+            let header = unsafe { &*cmsg };
+            if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == libc::SCM_CREDENTIALS {
+                let credentials: libc::ucred =
+                    unsafe { ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast()) };
+                pid = Some(credentials.pid);
+            }
+            if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == libc::SCM_RIGHTS {
+                let data = unsafe { libc::CMSG_DATA(cmsg) };
+                let len = header.cmsg_len as usize - (data as usize - cmsg as usize);
+                for i in 0..len / mem::size_of::<RawFd>() {
+                    let fd: RawFd = unsafe { ptr::read_unaligned(data.cast::<RawFd>().add(i)) };
+                    fds.push(unsafe { OwnedFd::from_raw_fd(fd) });
+                }
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+        }
+        Ok((n as usize, pid, fds))
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NotifySocket;
+    use std::os::{
+        fd::OwnedFd,
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    };
+
+    #[test]
+    fn messages() {
+        let socket = NotifySocket::new().unwrap();
+        assert!(socket.read(0).is_empty());
+        let name = socket.address.strip_prefix('@').unwrap();
+        let address = SocketAddr::from_abstract_name(name).unwrap();
+        let client = UnixDatagram::unbound().unwrap();
+        client
+            .send_to_addr(b"READY=1\nSTATUS=up", &address)
+            .unwrap();
+        client.send_to_addr(b"WATCHDOG=1", &address).unwrap();
+        let group = unsafe { libc::getpgid(0) } as u32;
+        assert_eq!(socket.read(group), ["READY=1", "STATUS=up", "WATCHDOG=1"]);
+        // from a process which isn't in the group of the child
+        client.send_to_addr(b"READY=1", &address).unwrap();
+        assert!(socket.read(group + 1).is_empty());
+        // the same socket once given back after a re-exec
+        let address = socket.address.clone();
+        let socket = NotifySocket::from_fd(OwnedFd::from(socket.socket));
+        assert_eq!(socket.address, address);
+        assert_ne!(NotifySocket::new().unwrap().address, address);
+    }
+}
@@ -0,0 +1,277 @@
+//! Listening sockets bound once by taskmaster and passed to every child, like the socket
+//! activation of systemd: the childs find them from the file descriptor 3, with `LISTEN_FDS`
+//! and `LISTEN_PID` in their environment.
+
+use std::{
+    error::Error,
+    fs, io,
+    net::TcpListener,
+    os::{
+        fd::{AsRawFd, OwnedFd, RawFd},
+        unix::{
+            fs::FileTypeExt,
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::PathBuf,
+    ptr,
+};
+use tracing::warn;
+
+pub const LISTEN_FDS_ENV: &str = "LISTEN_FDS";
+pub const LISTEN_PID_ENV: &str = "LISTEN_PID";
+/// the TCP states of /proc/net/tcp in which the connection is still open on the server side:
+/// ESTABLISHED and CLOSE_WAIT
+const TCP_OPEN: [&str; 2] = ["01", "08"];
+/// the state of a connected unix socket in /proc/net/unix
+const UNIX_CONNECTED: &str = "03";
+/// the first file descriptor passed to the childs
+pub const LISTEN_FDS_START: RawFd = 3;
+/// LISTEN_PID is the pid of the exec'd process, only known once forked: the command is run
+/// through a shell which sets it to its own pid before replacing itself by the command
+pub const LISTEN_PID_SHIM: &str = "export LISTEN_PID=$$; exec \"$0\" \"$@\"";
+
+#[derive(Debug, PartialEq)]
+enum Address {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+/// `tcp:<host>:<port>` or `unix:<path>`
+fn parse(address: &str) -> Result<Address, String> {
+    let invalid =
+        || format!("invalid socket `{address}`, expected tcp:<host>:<port> or unix:<path>");
+    match address.split_once(':').ok_or_else(invalid)? {
+        ("tcp", host_port) => {
+            let (host, port) = host_port.rsplit_once(':').ok_or_else(invalid)?;
+            if host.is_empty() || port.parse::<u16>().is_err() {
+                return Err(invalid());
+            }
+            Ok(Address::Tcp(host_port.to_string()))
+        }
+        ("unix", path) if !path.is_empty() => Ok(Address::Unix(PathBuf::from(path))),
+        _ => Err(invalid()),
+    }
+}
+
+/// check the address without binding it
+pub fn validate(address: &str) -> Result<(), String> {
+    parse(address).map(|_| ())
+}
+
+/// A listening socket shared by the childs of a program
+#[derive(Debug)]
+pub struct BoundSocket {
+    pub address: String,
+    fd: OwnedFd,
+}
+
+impl BoundSocket {
+    pub fn bind(address: &str) -> Result<Self, Box<dyn Error>> {
+        let fd = match parse(address)? {
+            Address::Tcp(host_port) => OwnedFd::from(TcpListener::bind(host_port)?),
+            Address::Unix(path) => {
+                // left behind by a previous taskmaster, unless something still listens on it
+                if fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_socket()) {
+                    if UnixStream::connect(&path).is_ok() {
+                        return Err(format!("{} is already in use", path.display()).into());
+                    }
+                    fs::remove_file(&path)?;
+                }
+                OwnedFd::from(UnixListener::bind(path)?)
+            }
+        };
+        Ok(BoundSocket {
+            address: address.to_string(),
+            fd,
+        })
+    }
+
+    /// a socket kept open across a re-exec
+    pub fn from_fd(address: &str, fd: OwnedFd) -> Self {
+        BoundSocket {
+            address: address.to_string(),
+            fd,
+        }
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+
+    /// a connection is waiting to be accepted
+    pub fn pending(&self) -> bool {
+        let mut pollfd = libc::pollfd {
+            fd: self.fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut pollfd, 1, 0) > 0 && pollfd.revents & libc::POLLIN != 0 }
+    }
+
+    /// close the connections waiting to be accepted
+    pub fn refuse(&self) {
+        while self.pending() {
+            let fd = unsafe { libc::accept(self.fd(), ptr::null_mut(), ptr::null_mut()) };
+            if fd < 0 {
+                return;
+            }
+            unsafe { libc::close(fd) };
+        }
+    }
+
+    /// the connections accepted by the childs which are still open, from /proc/net
+    pub fn connections(&self) -> io::Result<usize> {
+        match parse(&self.address) {
+            Ok(Address::Tcp(_)) => {
+                let port = TcpListener::from(self.fd.try_clone()?).local_addr()?.port();
+                let port = format!(":{port:04X}");
+                let mut count = 0;
+                for table in ["/proc/net/tcp", "/proc/net/tcp6"] {
+                    let table = match fs::read_to_string(table) {
+                        Ok(table) => table,
+                        // without IPv6
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                        Err(e) => return Err(e),
+                    };
+                    count += table
+                        .lines()
+                        .skip(1)
+                        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+                        .filter(|f| {
+                            f.len() > 3 && f[1].ends_with(&port) && TCP_OPEN.contains(&f[3])
+                        })
+                        .count();
+                }
+                Ok(count)
+            }
+            Ok(Address::Unix(path)) => {
+                let path = format!(" {}", path.display());
+                Ok(fs::read_to_string("/proc/net/unix")?
+                    .lines()
+                    .skip(1)
+                    .filter(|line| {
+                        line.split_whitespace().nth(5) == Some(UNIX_CONNECTED)
+                            && line.ends_with(&path)
+                    })
+                    .count())
+            }
+            Err(_) => Ok(0),
+        }
+    }
+}
+
+impl Drop for BoundSocket {
+    fn drop(&mut self) {
+        if let Ok(Address::Unix(path)) = parse(&self.address) {
+            if let Err(e) = fs::remove_file(&path) {
+                warn!(path = %path.display(), error = %e, "removing the socket");
+            }
+        }
+    }
+}
+
+/// move the sockets to the file descriptors from 3 in the forked child, without their
+/// close-on-exec flag. async-signal-safe
+pub fn install(fds: &mut [RawFd]) -> io::Result<()> {
+    let end = LISTEN_FDS_START + fds.len() as RawFd;
+    // out of the way first, a socket could already be on one of the targets
+    for fd in fds.iter_mut() {
+        *fd = unsafe { libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, end) };
+        if *fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    for (target, &fd) in (LISTEN_FDS_START..).zip(fds.iter()) {
+        if unsafe { libc::dup2(fd, target) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, validate, Address, BoundSocket};
+    use std::{
+        io::Read,
+        io::Write,
+        net::TcpStream,
+        os::unix::net::{UnixListener, UnixStream},
+        path::PathBuf,
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn addresses() {
+        assert_eq!(
+            parse("tcp:127.0.0.1:8080"),
+            Ok(Address::Tcp("127.0.0.1:8080".to_string()))
+        );
+        assert_eq!(
+            parse("tcp:[::1]:80"),
+            Ok(Address::Tcp("[::1]:80".to_string()))
+        );
+        assert_eq!(
+            parse("unix:/run/app.sock"),
+            Ok(Address::Unix(PathBuf::from("/run/app.sock")))
+        );
+        for invalid in [
+            "127.0.0.1:80",
+            "tcp:8080",
+            "tcp::80",
+            "tcp:a:http",
+            "unix:",
+            "udp:a:1",
+        ] {
+            assert!(validate(invalid).is_err(), "{invalid}");
+        }
+    }
+    #[test]
+    fn bind() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("app.sock");
+        let address = format!("unix:{}", path.display());
+        let socket = BoundSocket::bind(&address).unwrap();
+        assert!(UnixStream::connect(&path).is_ok());
+        assert!(BoundSocket::bind(&address).is_err());
+        drop(socket);
+        assert!(!path.exists());
+        // the stale socket of a previous run is replaced
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        drop(BoundSocket::bind(&address).unwrap());
+        assert!(!path.exists());
+
+        let tcp = BoundSocket::bind("tcp:127.0.0.1:0").unwrap();
+        assert!(!tcp.pending());
+        let listener = std::net::TcpListener::from(tcp.fd.try_clone().unwrap());
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        client.write_all(b"hi").unwrap();
+        let mut buf = [0; 2];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hi");
+        assert_eq!(tcp.connections().unwrap(), 1);
+        drop(server);
+        assert_eq!(tcp.connections().unwrap(), 0);
+    }
+    #[test]
+    fn connections() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("app.sock");
+        let socket = BoundSocket::bind(&format!("unix:{}", path.display())).unwrap();
+        let listener = UnixListener::from(socket.fd.try_clone().unwrap());
+        let _client = UnixStream::connect(&path).unwrap();
+        assert!(socket.pending());
+        assert_eq!(socket.connections().unwrap(), 0);
+        let (server, _) = listener.accept().unwrap();
+        assert!(!socket.pending());
+        assert_eq!(socket.connections().unwrap(), 1);
+        drop(server);
+        assert_eq!(socket.connections().unwrap(), 0);
+        let _refused = UnixStream::connect(&path).unwrap();
+        socket.refuse();
+        assert!(!socket.pending());
+    }
+}
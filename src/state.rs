@@ -0,0 +1,662 @@
+//! State file, to take back the running childs after taskmaster restarts.
+//!
+//! The file is rewritten whenever a child starts or stops. A process is only adopted back if its
+//! pid still refers to the same process: same boot, and same start time.
+//!
+//! The same state is given to taskmaster when it re-executes itself, with the exited childs and
+//! the pipes of the event listeners, so nothing is lost.
+
+use crate::{
+    config::Config,
+    program::{
+        child::{Child, Process, Status},
+        event::{Listener, ListenerState},
+        readiness::NotifySocket,
+        socket::BoundSocket,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    error::Error,
+    fs::{self, File},
+    io::{self, Read, Seek, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::process::CommandExt,
+    },
+    path::{Path, PathBuf},
+    process::{ChildStdin, ChildStdout, Command},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::{debug, error, info, warn};
+
+/// the fd of the state given to the re-executed taskmaster
+pub const REEXEC_ENV: &str = "TASKMASTER_REEXEC";
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+struct State {
+    boot_id: String,
+    processes: Vec<Entry>,
+    /// the programs waiting for their childs to stop before restarting, on re-exec
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    force_restart: Vec<String>,
+    /// the programs stopped on request, which connections don't start, on re-exec
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    stopped: Vec<String>,
+    /// the programs waiting for their dependencies to be running, on re-exec
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    waits_dependencies: Vec<String>,
+    /// the listening sockets of the programs, kept open across a re-exec
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sockets: Vec<Socket>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Socket {
+    program: String,
+    address: String,
+    fd: RawFd,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Entry {
+    program: String,
+    index: usize,
+    pid: u32,
+    /// in clock ticks after boot, from `/proc/<pid>/stat`. 0 once the process exited
+    start_time: u64,
+    restarts: usize,
+    /// unix timestamp of the last status change, in milliseconds
+    since: u64,
+    #[serde(default)]
+    status: SavedStatus,
+    /// a stop has been requested, the process isn't restarted
+    #[serde(default)]
+    stopped: bool,
+    /// the pipes of an event listener, kept open across a re-exec
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    listener: Option<Pipes>,
+    /// the notify socket of the child, kept open across a re-exec
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    notify: Option<RawFd>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(tag = "state", rename_all = "lowercase")]
+enum SavedStatus {
+    Stopped,
+    Finished {
+        code: i32,
+    },
+    Terminated {
+        signal: i32,
+    },
+    Terminating,
+    Starting,
+    #[default]
+    Running,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+struct Pipes {
+    stdin: RawFd,
+    stdout: RawFd,
+    /// the listener sent READY and waits for an event
+    ready: bool,
+}
+
+impl SavedStatus {
+    fn new(status: Status) -> Self {
+        match status {
+            Status::Stopped(_) => SavedStatus::Stopped,
+            Status::Finished(_, code) => SavedStatus::Finished { code },
+            Status::Terminated(_, signal) => SavedStatus::Terminated { signal },
+            Status::Terminating(_) => SavedStatus::Terminating,
+            Status::Starting(_) => SavedStatus::Starting,
+            Status::Running(_) => SavedStatus::Running,
+        }
+    }
+
+    fn status(self, since: Instant) -> Status {
+        match self {
+            SavedStatus::Stopped => Status::Stopped(since),
+            SavedStatus::Finished { code } => Status::Finished(since, code),
+            SavedStatus::Terminated { signal } => Status::Terminated(since, signal),
+            SavedStatus::Terminating => Status::Terminating(since),
+            SavedStatus::Starting => Status::Starting(since),
+            SavedStatus::Running => Status::Running(since),
+        }
+    }
+}
+
+impl State {
+    /// the childs of every program. only the running ones, unless for a re-exec
+    fn new(config: &Config, reexec: bool) -> Self {
+        let processes = config
+            .program
+            .iter()
+            .flat_map(|p| p.childs.iter().map(move |c| (p, c)))
+            .filter(|(_, c)| reexec || c.status.is_running())
+            .filter_map(|(p, c)| {
+                let pid = c.process.id();
+                let start_time = match c.status.is_running() {
+                    true => c.start_time?,
+                    false => 0,
+                };
+                let listener = match (&c.listener, c.process.pipes()) {
+                    (Some(listener), (Some(stdin), Some(stdout))) if reexec => Some(Pipes {
+                        stdin,
+                        stdout,
+                        ready: matches!(listener.state, ListenerState::Ready),
+                    }),
+                    _ => None,
+                };
+                Some(Entry {
+                    program: p.name.clone(),
+                    index: c.index,
+                    pid,
+                    start_time,
+                    restarts: c.restarts,
+                    since: unix(c.status.get_instant()),
+                    status: SavedStatus::new(c.status),
+                    stopped: c.stopped,
+                    listener,
+                    notify: c.notify.as_ref().filter(|_| reexec).map(NotifySocket::fd),
+                })
+            })
+            .collect();
+        let (force_restart, stopped, waits_dependencies, sockets) = match reexec {
+            true => (
+                config
+                    .program
+                    .iter()
+                    .filter(|p| p.force_restart)
+                    .map(|p| p.name.clone())
+                    .collect(),
+                config
+                    .program
+                    .iter()
+                    .filter(|p| p.stopped)
+                    .map(|p| p.name.clone())
+                    .collect(),
+                config
+                    .program
+                    .iter()
+                    .filter(|p| p.waits_dependencies)
+                    .map(|p| p.name.clone())
+                    .collect(),
+                config
+                    .program
+                    .iter()
+                    .flat_map(|p| {
+                        p.bound.iter().map(|socket| Socket {
+                            program: p.name.clone(),
+                            address: socket.address.clone(),
+                            fd: socket.fd(),
+                        })
+                    })
+                    .collect(),
+            ),
+            false => (Vec::new(), Vec::new(), Vec::new(), Vec::new()),
+        };
+        State {
+            boot_id: boot_id(),
+            processes,
+            force_restart,
+            stopped,
+            waits_dependencies,
+            sockets,
+        }
+    }
+
+    /// the pipes of the listeners, the notify sockets and the listening sockets, inherited on
+    /// re-exec
+    fn fds(&self) -> Vec<RawFd> {
+        let fds = self.processes.iter().flat_map(|e| {
+            let pipes = e.listener.map(|p| [p.stdin, p.stdout]);
+            pipes.into_iter().flatten().chain(e.notify)
+        });
+        fds.chain(self.sockets.iter().map(|s| s.fd)).collect()
+    }
+
+    /// give the processes back to their programs. on re-exec, the exited childs and the
+    /// event listeners are restored too
+    fn restore(self, config: &mut Config, reexec: bool) {
+        for program in &mut config.program {
+            let sockets: Vec<_> = self
+                .sockets
+                .iter()
+                .filter(|s| s.program == program.name)
+                .map(|s| (s.address.as_str(), unsafe { OwnedFd::from_raw_fd(s.fd) }))
+                .collect();
+            // closed if the program doesn't want the same sockets anymore
+            if sockets
+                .iter()
+                .map(|(address, _)| address)
+                .eq(&program.sockets)
+            {
+                program.bound = sockets
+                    .into_iter()
+                    .map(|(address, fd)| BoundSocket::from_fd(address, fd))
+                    .collect();
+            }
+            let mut childs = Vec::new();
+            for entry in self.processes.iter().filter(|e| e.program == program.name) {
+                let running = entry.status.status(Instant::now()).is_running();
+                if running && start_time(entry.pid) != Some(entry.start_time) {
+                    debug!(name = program.name, pid = entry.pid, "Process is gone");
+                    continue;
+                }
+                if !running && !reexec {
+                    continue;
+                }
+                if entry.index >= program.processes as usize
+                    || program.is_listener() != entry.listener.is_some()
+                {
+                    warn!(
+                        name = program.name,
+                        pid = entry.pid,
+                        "The program changed, leaving the process running without adopting it"
+                    );
+                    continue;
+                }
+                let process = match running {
+                    true => match Process::adopt(entry.pid) {
+                        Ok(process) => process,
+                        Err(e) => {
+                            error!(pid = entry.pid, error = %e, "adopting the process");
+                            continue;
+                        }
+                    },
+                    false => Process::Exited(entry.pid),
+                };
+                let since = instant(entry.since);
+                let mut child = Child::adopted(
+                    process,
+                    entry.index,
+                    entry.status.status(since),
+                    entry.restarts,
+                );
+                child.stopped = entry.stopped;
+                child.start_time = Some(entry.start_time).filter(|_| running);
+                if let Some(fd) = entry.notify {
+                    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+                    child.notify = Some(NotifySocket::from_fd(fd));
+                }
+                if let Some(pipes) = entry.listener {
+                    let (stdin, stdout) = unsafe {
+                        (
+                            ChildStdin::from(OwnedFd::from_raw_fd(pipes.stdin)),
+                            ChildStdout::from(OwnedFd::from_raw_fd(pipes.stdout)),
+                        )
+                    };
+                    child.process.attach((stdin, stdout));
+                    let mut listener = Listener::default();
+                    if pipes.ready {
+                        listener.state = ListenerState::Ready;
+                    }
+                    child.listener = Some(listener);
+                }
+                if running {
+                    info!(
+                        name = program.name,
+                        pid = entry.pid,
+                        "Adopting running process"
+                    );
+                }
+                childs.push(child);
+            }
+            program.force_restart = self.force_restart.contains(&program.name);
+            program.stopped = self.stopped.contains(&program.name);
+            program.waits_dependencies = self.waits_dependencies.contains(&program.name);
+            if !childs.is_empty() {
+                if let Err(e) = program.readopt(childs) {
+                    error!(
+                        name = program.name,
+                        error = e,
+                        "starting the missing processes"
+                    );
+                }
+            }
+        }
+        let removed = self
+            .sockets
+            .iter()
+            .filter(|s| !config.program.iter().any(|p| p.name == s.program));
+        for socket in removed {
+            drop(unsafe { OwnedFd::from_raw_fd(socket.fd) });
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StateFile {
+    path: PathBuf,
+    /// the state changes of the config at the last save
+    saved: Option<usize>,
+}
+
+impl StateFile {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        StateFile {
+            path: path.as_ref().to_path_buf(),
+            saved: None,
+        }
+    }
+
+    /// adopt the processes left running by the previous taskmaster
+    pub fn restore(&self, config: &mut Config) {
+        let state: State = match fs::read_to_string(&self.path) {
+            Ok(raw) => match serde_json::from_str(&raw) {
+                Ok(state) => state,
+                Err(e) => {
+                    error!(error = %e, path = %self.path.display(), "invalid state file");
+                    return;
+                }
+            },
+            Err(_) => return,
+        };
+        if state.boot_id != boot_id() {
+            info!("The state file is from a previous boot, ignoring it");
+            return;
+        }
+        state.restore(config, false);
+    }
+
+    /// write the running childs, if one changed state since the last save
+    pub fn save(&mut self, config: &Config) -> Result<(), Box<dyn Error>> {
+        if self.saved == Some(config.changes) {
+            return Ok(());
+        }
+        let state = State::new(config, false);
+        // written next to it then renamed, to never leave a truncated file
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&state)?)?;
+        fs::rename(&tmp, &self.path)?;
+        self.saved = Some(config.changes);
+        Ok(())
+    }
+
+    /// nothing is left running after a clean exit
+    pub fn remove(&self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// replace taskmaster by a new execution of its binary, which takes the childs back.
+/// only returns on failure
+pub fn reexec(config: &Config) -> Box<dyn Error> {
+    let state = State::new(config, true);
+    // in memory, only reachable by the new execution through its inherited fd
+    let memfd = match state_memfd(&state) {
+        Ok(memfd) => memfd,
+        Err(e) => return format!("saving the state: {e}").into(),
+    };
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => return e.into(),
+    };
+    // the binary has been replaced by the upgrade
+    let exe = match exe.to_str().and_then(|e| e.strip_suffix(" (deleted)")) {
+        Some(exe) => PathBuf::from(exe),
+        None => exe,
+    };
+    // the fds of the state, and the state itself, must survive exec
+    let mut fds = state.fds();
+    fds.push(memfd.as_raw_fd());
+    info!(exe = %exe.display(), "Executing the new binary");
+    let error = match set_cloexec(&fds, false) {
+        Ok(()) => Command::new(&exe)
+            .args(env::args_os().skip(1))
+            .env(REEXEC_ENV, memfd.as_raw_fd().to_string())
+            .exec(),
+        Err(e) => e,
+    };
+    // or the next childs would inherit them
+    if let Err(e) = set_cloexec(&fds, true) {
+        error!(error = %e, "setting close-on-exec back");
+    }
+    format!("executing {}: {error}", exe.display()).into()
+}
+
+/// the state written to an anonymous file
+fn state_memfd(state: &State) -> Result<File, Box<dyn Error>> {
+    let fd = unsafe { libc::memfd_create(c"taskmaster-reexec".as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(serde_json::to_string(state)?.as_bytes())?;
+    file.rewind()?;
+    Ok(file)
+}
+
+fn set_cloexec(fds: &[RawFd], cloexec: bool) -> io::Result<()> {
+    let flags = match cloexec {
+        true => libc::FD_CLOEXEC,
+        false => 0,
+    };
+    let mut result = Ok(());
+    // all of them, even after a failure
+    for &fd in fds {
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, flags) } != 0 {
+            result = Err(io::Error::last_os_error());
+        }
+    }
+    result
+}
+
+/// if taskmaster has been re-executed, take back the childs of the previous execution
+pub fn restore_reexec(config: &mut Config) -> Result<bool, Box<dyn Error>> {
+    let Some(fd) = env::var_os(REEXEC_ENV) else {
+        return Ok(false);
+    };
+    // not given to the childs
+    env::remove_var(REEXEC_ENV);
+    let fd: RawFd = fd
+        .to_str()
+        .and_then(|fd| fd.parse().ok())
+        .ok_or(format!("invalid {REEXEC_ENV}"))?;
+    let mut raw = String::new();
+    unsafe { File::from_raw_fd(fd) }.read_to_string(&mut raw)?;
+    let state: State = serde_json::from_str(&raw)?;
+    set_cloexec(&state.fds(), true)?;
+    info!(
+        processes = state.processes.len(),
+        "Restoring the state after re-exec"
+    );
+    state.restore(config, true);
+    Ok(true)
+}
+
+fn boot_id() -> String {
+    fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .map(|id| id.trim().to_string())
+        .unwrap_or_default()
+}
+
+pub fn start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    parse_start_time(&stat)
+}
+
+/// the 22nd field of `/proc/<pid>/stat`
+fn parse_start_time(stat: &str) -> Option<u64> {
+    // the command name can contain spaces and parentheses, it ends at the last one
+    stat[stat.rfind(')')? + 1..]
+        .split_whitespace()
+        .nth(19)?
+        .parse()
+        .ok()
+}
+
+fn unix(instant: Instant) -> u64 {
+    (SystemTime::now() - instant.elapsed())
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn instant(unix: u64) -> Instant {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let ago = now.saturating_sub(Duration::from_millis(unix));
+    Instant::now().checked_sub(ago).unwrap_or_else(Instant::now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        instant, parse_start_time, set_cloexec, start_time, state_memfd, unix, Entry, Pipes,
+        SavedStatus, State, StateFile,
+    };
+    use crate::{
+        config::Config,
+        program::child::{Process, Status},
+    };
+    use std::{
+        fs,
+        io::Read,
+        os::fd::AsRawFd,
+        process::{self, Command},
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn reexec_memfd() {
+        let state = State {
+            boot_id: "boot".to_string(),
+            ..Default::default()
+        };
+        let mut memfd = state_memfd(&state).unwrap();
+        let cloexec = |fd| unsafe { libc::fcntl(fd, libc::F_GETFD) } & libc::FD_CLOEXEC;
+        // only inherited during exec
+        assert_ne!(cloexec(memfd.as_raw_fd()), 0);
+        set_cloexec(&[memfd.as_raw_fd()], false).unwrap();
+        assert_eq!(cloexec(memfd.as_raw_fd()), 0);
+        set_cloexec(&[memfd.as_raw_fd()], true).unwrap();
+        assert_ne!(cloexec(memfd.as_raw_fd()), 0);
+        let mut raw = String::new();
+        memfd.read_to_string(&mut raw).unwrap();
+        assert_eq!(serde_json::from_str::<State>(&raw).unwrap(), state);
+    }
+    #[test]
+    fn reexec_waits_dependencies() {
+        let mut config = Config::load("tests/depends_on.toml").unwrap();
+        config.start_program("web").unwrap();
+        let state = State::new(&config, true);
+        let mut restored = Config::load("tests/depends_on.toml").unwrap();
+        state.restore(&mut restored, true);
+        assert!(restored.program[0].waits_dependencies);
+        assert!(!restored.program[1].waits_dependencies);
+    }
+    #[test]
+    fn saved_on_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let mut config: Config =
+            toml::from_str("[[program]]\nname = \"long\"\ncommand = \"sleep\"\nargs = [\"30\"]")
+                .unwrap();
+        config.program[0].start().unwrap();
+        config.dispatch_events();
+        let mut file = StateFile::new(&path);
+        file.save(&config).unwrap();
+        let raw = fs::read_to_string(&path).unwrap();
+        let state: State = serde_json::from_str(&raw).unwrap();
+        assert_eq!(state.processes.len(), 1);
+        assert_eq!(
+            state.processes[0].start_time,
+            config.program[0].childs[0].start_time.unwrap()
+        );
+        // only written again once a child changes state
+        fs::remove_file(&path).unwrap();
+        file.save(&config).unwrap();
+        assert!(!path.exists());
+        config.program[0].stop();
+        config.dispatch_events();
+        file.save(&config).unwrap();
+        assert!(path.exists());
+        config.program[0].kill();
+    }
+    #[test]
+    fn stat() {
+        let stat =
+            "1234 (a) b) S 1 1234 1234 0 -1 4194560 146 0 0 0 0 0 0 0 20 0 1 0 98765 2367488";
+        assert_eq!(parse_start_time(stat), Some(98765));
+        assert_eq!(parse_start_time("1234 (a)"), None);
+    }
+    #[test]
+    fn own_start_time() {
+        let first = start_time(process::id());
+        assert!(first.is_some());
+        assert_eq!(first, start_time(process::id()));
+    }
+    #[test]
+    fn adopt() {
+        // reaped through the adopted process
+        let pid = Command::new("sleep").arg("10").spawn().unwrap().id();
+        let mut process = Process::adopt(pid).unwrap();
+        assert!(process.pidfd().is_some());
+        assert!(process.try_wait().unwrap().is_none());
+        unsafe { libc::kill(pid as i32, libc::SIGKILL) };
+        let status = loop {
+            if let Some(status) = process.try_wait().unwrap() {
+                break status;
+            }
+        };
+        // still a child of the test, so its exit status is known
+        assert_eq!(
+            status.and_then(|s| std::os::unix::process::ExitStatusExt::signal(&s)),
+            Some(libc::SIGKILL)
+        );
+    }
+    #[test]
+    fn saved_status() {
+        let since = Instant::now() - Duration::from_secs(5);
+        for status in [
+            Status::Stopped(since),
+            Status::Finished(since, 3),
+            Status::Terminated(since, 9),
+            Status::Terminating(since),
+            Status::Starting(since),
+            Status::Running(since),
+        ] {
+            let restored = SavedStatus::new(status).status(instant(unix(since)));
+            assert!(restored.eq_ignore_instant(&status));
+            let drift = restored.get_instant().elapsed().abs_diff(since.elapsed());
+            assert!(drift < Duration::from_millis(10));
+        }
+    }
+    #[test]
+    fn entry() {
+        let entry = Entry {
+            program: "web".to_string(),
+            index: 1,
+            pid: 42,
+            start_time: 0,
+            restarts: 2,
+            since: 1000,
+            status: SavedStatus::Finished { code: 3 },
+            stopped: true,
+            notify: None,
+            listener: Some(Pipes {
+                stdin: 7,
+                stdout: 8,
+                ready: true,
+            }),
+        };
+        let raw = serde_json::to_string(&entry).unwrap();
+        assert!(raw.contains(r#""status":{"state":"finished","code":3}"#));
+        assert_eq!(serde_json::from_str::<Entry>(&raw).unwrap(), entry);
+        // written before the statuses were saved
+        let old: Entry = serde_json::from_str(
+            r#"{"program":"web","index":0,"pid":42,"start_time":1,"restarts":0,"since":1}"#,
+        )
+        .unwrap();
+        assert_eq!(old.status, SavedStatus::Running);
+        assert_eq!(old.listener, None);
+        assert!(!old.stopped);
+    }
+}
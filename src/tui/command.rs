@@ -8,7 +8,9 @@ pub enum Command {
     Stop(String),
     Restart(String),
     Reload(String),
+    Reexec,
     LogLevel(Level),
+    Status(String),
 }
 impl FromStr for Command {
     type Err = ();
@@ -30,6 +32,10 @@ impl FromStr for Command {
             return Ok(Self::Restart(arg));
         } else if "reload".starts_with(cmd) {
             return Ok(Self::Reload(arg));
+        } else if "reexec".starts_with(cmd) && arg.is_empty() {
+            return Ok(Self::Reexec);
+        } else if "status".starts_with(cmd) {
+            return Ok(Self::Status(arg));
         } else if "loglevel".starts_with(cmd) && !arg.is_empty() {
             return Ok(Self::LogLevel(Level::from_str(&arg).map_err(|_| ())?));
         }
@@ -38,5 +44,5 @@ impl FromStr for Command {
 }
 impl Command {
     pub const HELP: &'static str =
-        "quit (2x to force) | start <name?> | stop <name?> | restart <name?> | reload <path?> | loglevel <level>";
+        "quit (2x to force) | start <name?> | stop <name?> | restart <name?> | reload <path?> | reexec | status <name?> | loglevel <level>";
 }
@@ -2,7 +2,7 @@ pub mod command;
 mod status;
 
 pub use self::command::Command;
-use crate::program::Program;
+use crate::{config::Group, program::Program};
 use crossterm::{
     cursor::{Hide, Show},
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
@@ -68,7 +68,7 @@ impl Tui {
     ///
     /// [`Draw`]: tui::Terminal::draw
     /// [`rendering`]: crate::ui:render
-    pub fn draw(&mut self, programs: &[Program]) -> io::Result<()> {
+    pub fn draw(&mut self, programs: &[Program], groups: &[Group]) -> io::Result<()> {
         self.terminal.draw(|frame| {
             let layout = Layout::default()
                 .direction(Direction::Vertical)
@@ -114,11 +114,11 @@ impl Tui {
             let widths = [
                 Constraint::Percentage(25),
                 Constraint::Fill(1),
-                Constraint::Percentage(10),
+                Constraint::Percentage(15),
                 Constraint::Length(15),
             ];
             frame.render_stateful_widget(
-                status::status(programs).widths(widths).block(
+                status::status(programs, groups).widths(widths).block(
                     Block::default()
                         .title("Status")
                         .title_alignment(Alignment::Center)
@@ -233,6 +233,11 @@ impl Tui {
         Ok(None)
     }
 
+    /// if input has already been read from the terminal, and is waiting for tick
+    pub fn has_pending_input(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(event::poll(Duration::ZERO)?)
+    }
+
     /// check for a valid command and reset the input
     pub fn handle_enter(&mut self) -> Option<Command> {
         let input = self.input.value().to_string();
@@ -1,51 +1,77 @@
-use crate::program::{child::Status, Program};
+use crate::{
+    config::Group,
+    program::{child::Status, Program},
+};
 use ratatui::{
-    style::Color,
+    style::{Color, Style, Stylize},
     widgets::{Cell, Row, Table},
 };
-pub fn status(programs: &[Program]) -> Table {
+pub fn status<'a>(programs: &'a [Program], groups: &[Group]) -> Table<'a> {
     let mut rows = vec![Row::new(vec!["Name", "Status", "Processes", "Last update"])];
     rows.push(Row::new(vec!["╺━━━━━╸"]));
-    for prog in programs {
+    let push = |rows: &mut Vec<Row<'a>>, prog: &'a Program| {
         let mut status_rows = prog.status();
         if !status_rows.is_empty() {
             status_rows.push(Row::new(vec!["╺━━━━━╸"]));
         }
-        rows.extend(status_rows.clone());
+        rows.extend(status_rows);
+    };
+    for group in groups {
+        rows.push(Row::new([Cell::from(format!("{}:*", group.name))]).style(Style::new().bold()));
+        for prog in programs.iter().filter(|p| group.programs.contains(&p.name)) {
+            push(&mut rows, prog);
+        }
+    }
+    // the programs of no group
+    for prog in programs
+        .iter()
+        .filter(|p| !groups.iter().any(|g| g.programs.contains(&p.name)))
+    {
+        push(&mut rows, prog);
     }
     Table::new(rows, &[])
 }
 
 impl Program {
-    pub fn status(&self) -> Vec<Row> {
-        let statuss = self.childs.iter().map(|c| c.status).collect::<Vec<_>>();
-        let mut vec: Vec<(Status, i32)> = Vec::new();
-        for status in statuss {
+    pub fn status(&self) -> Vec<Row<'_>> {
+        // the process numbers of the childs in each status
+        let mut vec: Vec<(Status, Vec<usize>)> = Vec::new();
+        for child in &self.childs {
+            let status = child.status;
             if let Some(v) = vec.iter_mut().find(|x| x.0.eq_ignore_instant(&status)) {
-                v.1 += 1;
+                v.1.push(child.index);
                 if v.0.get_instant() < status.get_instant() {
                     v.0 = status;
                 }
             } else {
-                vec.push((status, 1));
+                vec.push((status, vec![child.index]));
             }
         }
 
         let mut lines = vec![];
-        for (status, count) in vec {
+        for (status, indexes) in vec {
+            let mut processes = format!("{}/{}", indexes.len(), self.childs.len());
+            if self.childs.len() > 1 {
+                let indexes: Vec<_> = indexes.iter().map(usize::to_string).collect();
+                processes.push_str(&format!(" #{}", indexes.join(",")));
+            }
             lines.push(Row::new([
                 Cell::from(self.name.clone()),
                 Cell::from(status.to_string())
                     .style(status.color(&self.valid_exit_codes, self.stop_signal as i32)),
-                Cell::from(format!("{count}/{}", self.childs.len())),
+                Cell::from(processes),
                 Cell::from(format!("{:?}", status.get_instant().elapsed())),
             ]));
         }
 
         if lines.is_empty() {
+            let status = match self.waits_dependencies {
+                true => "Waiting for its dependencies",
+                false => "No processes",
+            };
             lines.push(Row::new([
                 self.name.clone(),
-                "No processes".to_string(),
+                status.to_string(),
                 "0".to_string(),
                 "".to_string(),
             ]))
@@ -0,0 +1,201 @@
+//! Sleeping until something happens: a child changing state, a readable file descriptor or a
+//! timer expiring.
+//!
+//! SIGCHLD and the signals handled by taskmaster are turned into a readable byte with the
+//! self-pipe trick, so they can be waited for with `poll` alongside the sockets, without racing
+//! with the checks of the main loop. The handlers only touch atomics and write to the pipe,
+//! everything else is done by the main loop from `take_signals`.
+
+use libc::{c_int, c_void};
+use std::{
+    io,
+    os::fd::RawFd,
+    sync::{
+        atomic::{AtomicI32, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+/// the self-pipe, created once for the whole process
+static PIPE: [AtomicI32; 2] = [AtomicI32::new(-1), AtomicI32::new(-1)];
+static SETUP: Mutex<()> = Mutex::new(());
+/// bitmask of the signals received since the last `take_signals`
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+/// wake the main loop up. async-signal-safe
+pub fn wake() {
+    let fd = PIPE[1].load(Ordering::Relaxed);
+    if fd < 0 {
+        return;
+    }
+    unsafe {
+        let errno = *libc::__errno_location();
+        // the pipe being full is fine, the main loop will wake up anyway
+        libc::write(fd, [0u8].as_ptr() as *const c_void, 1);
+        *libc::__errno_location() = errno;
+    }
+}
+
+extern "C" fn signal_handler(signal: c_int) {
+    PENDING.fetch_or(1 << signal, Ordering::Relaxed);
+    wake()
+}
+
+fn install(signal: c_int, handler: extern "C" fn(c_int), flags: c_int) -> io::Result<()> {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as *const () as usize;
+        action.sa_flags = flags;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// record the signals instead of their default action, and wake the main loop up on them
+pub fn handle_signals(signals: &[c_int]) -> io::Result<()> {
+    for &signal in signals {
+        install(signal, signal_handler, libc::SA_RESTART)?;
+    }
+    Ok(())
+}
+
+/// the signals received since the last call
+pub fn take_signals() -> Vec<c_int> {
+    let pending = PENDING.swap(0, Ordering::Relaxed);
+    (1..64).filter(|s| pending & (1 << s) != 0).collect()
+}
+
+#[derive(Debug)]
+pub struct Waiter {
+    read: RawFd,
+}
+
+impl Waiter {
+    /// create the self-pipe and install the SIGCHLD handler, the first time.
+    /// SIGCHLD is then also returned by `take_signals`
+    pub fn new() -> io::Result<Self> {
+        let _guard = SETUP.lock().unwrap_or_else(|e| e.into_inner());
+        let read = PIPE[0].load(Ordering::Relaxed);
+        if read >= 0 {
+            return Ok(Waiter { read });
+        }
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        install(
+            libc::SIGCHLD,
+            signal_handler,
+            libc::SA_RESTART | libc::SA_NOCLDSTOP,
+        )?;
+        PIPE[1].store(fds[1], Ordering::Relaxed);
+        PIPE[0].store(fds[0], Ordering::Relaxed);
+        Ok(Waiter { read: fds[0] })
+    }
+
+    /// sleep until a child changes state, one of `fds` is readable, one of `write_fds` is
+    /// writable, `deadline` is reached, or a signal is received
+    pub fn wait(
+        &self,
+        fds: &[RawFd],
+        write_fds: &[RawFd],
+        deadline: Option<Instant>,
+    ) -> io::Result<()> {
+        let pollfd = |events| {
+            move |&fd| libc::pollfd {
+                fd,
+                events,
+                revents: 0,
+            }
+        };
+        let mut pollfds: Vec<_> = [self.read]
+            .iter()
+            .chain(fds)
+            .map(pollfd(libc::POLLIN))
+            .chain(write_fds.iter().map(pollfd(libc::POLLOUT)))
+            .collect();
+        let ret = unsafe {
+            libc::poll(
+                pollfds.as_mut_ptr(),
+                pollfds.len() as _,
+                timeout(deadline, Instant::now()),
+            )
+        };
+        if ret < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+        let mut buf = [0u8; 64];
+        while unsafe { libc::read(self.read, buf.as_mut_ptr() as *mut c_void, buf.len()) } > 0 {}
+        Ok(())
+    }
+}
+
+/// the poll timeout in milliseconds, rounded up to not wake up just before the deadline
+fn timeout(deadline: Option<Instant>, now: Instant) -> c_int {
+    deadline.map_or(-1, |deadline| {
+        let left = deadline.saturating_duration_since(now);
+        left.as_micros().div_ceil(1000).min(c_int::MAX as u128) as c_int
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{handle_signals, take_signals, timeout, Waiter};
+    use std::{
+        io::Write,
+        os::{fd::AsRawFd, unix::net::UnixStream},
+        process::Command,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn timeouts() {
+        let now = Instant::now();
+        assert_eq!(timeout(None, now), -1);
+        assert_eq!(timeout(Some(now - Duration::from_secs(1)), now), 0);
+        assert_eq!(timeout(Some(now + Duration::from_micros(1500)), now), 2);
+        assert_eq!(timeout(Some(now + Duration::from_secs(3)), now), 3000);
+    }
+    #[test]
+    fn signals() {
+        handle_signals(&[libc::SIGUSR2]).unwrap();
+        unsafe { libc::raise(libc::SIGUSR2) };
+        assert!(take_signals().contains(&libc::SIGUSR2));
+        assert!(!take_signals().contains(&libc::SIGUSR2));
+    }
+    #[test]
+    fn readable_fd() {
+        let waiter = Waiter::new().unwrap();
+        let (mut a, b) = UnixStream::pair().unwrap();
+        a.write_all(b"x").unwrap();
+        waiter.wait(&[b.as_raw_fd()], &[], None).unwrap();
+    }
+    #[test]
+    fn writable_fd() {
+        let waiter = Waiter::new().unwrap();
+        let (a, _b) = UnixStream::pair().unwrap();
+        let start = Instant::now();
+        waiter
+            .wait(&[], &[a.as_raw_fd()], Some(start + Duration::from_secs(5)))
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+    #[test]
+    fn child_exit() {
+        let waiter = Waiter::new().unwrap();
+        let mut child = Command::new("sleep").arg("0.1").spawn().unwrap();
+        let start = Instant::now();
+        waiter
+            .wait(&[], &[], Some(start + Duration::from_secs(5)))
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        child.wait().unwrap();
+    }
+}
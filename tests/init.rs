@@ -0,0 +1,89 @@
+//! taskmaster as the init process of a pid namespace, created with `unshare`
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, ExitStatus},
+};
+use tempfile::tempdir;
+
+/// run taskmaster as pid 1 with the given programs, or None if namespaces aren't available
+fn run_as_init(dir: &Path, programs: &str) -> Option<ExitStatus> {
+    let available = Command::new("unshare")
+        .args(["-Upfr", "--mount-proc", "true"])
+        .status()
+        .is_ok_and(|s| s.success());
+    if !available {
+        eprintln!("pid namespaces are not available, skipping");
+        return None;
+    }
+    let config = dir.join("taskmaster.toml");
+    let socket = dir.join("taskmaster.sock");
+    fs::write(
+        &config,
+        format!("socket = {:?}\n{programs}", socket.display().to_string()),
+    )
+    .unwrap();
+    let status = Command::new("unshare")
+        .args(["-Upfr", "--mount-proc"])
+        .arg(env!("CARGO_BIN_EXE_taskmaster"))
+        .arg("--headless")
+        .arg(&config)
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    Some(status)
+}
+
+#[test]
+fn exit_code() {
+    let dir = tempdir().unwrap();
+    let status = run_as_init(
+        dir.path(),
+        r#"
+[[program]]
+name = "ok"
+command = "true"
+[[program]]
+name = "failing"
+command = "sh"
+args = ["-c", "sleep 0.2; exit 3"]
+"#,
+    );
+    if let Some(status) = status {
+        assert_eq!(status.code(), Some(3));
+    }
+}
+
+#[test]
+fn ordered_stop() {
+    let dir = tempdir().unwrap();
+    let log = dir.path().join("stop.log");
+    let zombie = dir.path().join("zombie.pid");
+    // an orphan taskmaster doesn't know is reaped, and SIGTERM stops `second` before `first`
+    let status = run_as_init(
+        dir.path(),
+        &format!(
+            r#"
+[[program]]
+name = "first"
+command = "sh"
+args = ["-c", "trap 'echo first >> {log}; exit 0' TERM; while :; do sleep 0.1; done"]
+[[program]]
+name = "second"
+command = "sh"
+args = ["-c", "trap 'sleep 0.3; echo second >> {log}; exit 0' TERM; while :; do sleep 0.1; done"]
+[[program]]
+name = "trigger"
+command = "sh"
+args = ["-c", "env -u TASKMASTER_PROGRAM_NAME sh -c 'sleep 0.1 & echo $! > {zombie}'; sleep 1; [ -e /proc/$(cat {zombie}) ] && echo zombie >> {log}; kill -TERM 1"]
+"#,
+            log = log.display(),
+            zombie = zombie.display(),
+        ),
+    );
+    if let Some(status) = status {
+        assert_eq!(status.code(), Some(0));
+        assert_eq!(fs::read_to_string(&log).unwrap(), "second\nfirst\n");
+    }
+}
@@ -0,0 +1,12 @@
+import sys
+
+# supervisord style event listener, logging the events it receives on stderr
+while True:
+    sys.stdout.write("READY\n")
+    sys.stdout.flush()
+    header = dict(token.split(":") for token in sys.stdin.readline().split())
+    payload = sys.stdin.read(int(header["len"]))
+    sys.stderr.write(header["eventname"] + " " + payload + "\n")
+    sys.stderr.flush()
+    sys.stdout.write("RESULT 2\nOK")
+    sys.stdout.flush()
@@ -0,0 +1,16 @@
+import os
+import socket
+import sys
+import time
+
+# systemd style notifications: ready, a few watchdog pings, then hanging
+address = os.environ["NOTIFY_SOCKET"]
+if address.startswith("@"):
+    address = "\0" + address[1:]
+sock = socket.socket(socket.AF_UNIX, socket.SOCK_DGRAM)
+time.sleep(0.2)
+sock.sendto(b"READY=1\nSTATUS=ready", address)
+for _ in range(int(sys.argv[1]) if len(sys.argv) > 1 else 3):
+    time.sleep(0.1)
+    sock.sendto(b"WATCHDOG=1", address)
+time.sleep(1000)